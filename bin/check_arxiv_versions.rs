@@ -0,0 +1,23 @@
+/// Checks the latest arXiv version of every locally available id not yet checked,
+/// appending `id,version` lines to `checked_ids.csv`. Resumable, as already checked ids
+/// are skipped on restart.
+use ar5iv_util::local::{filter_list_to_check, CHECKED_IDS_FILEPATH, UNCHECKED_IDS_FILEPATH};
+use ar5iv_util::{logging, metrics};
+use ar5iv_util::remote::check_ids_http;
+use std::error::Error;
+use tracing::info;
+
+fn main() -> Result<(), Box<dyn Error>> {
+  logging::init();
+  let result = check_versions();
+  metrics::write_textfile("version_check", result.is_ok())?;
+  result
+}
+
+fn check_versions() -> Result<(), Box<dyn Error>> {
+  let ids_to_check = filter_list_to_check(UNCHECKED_IDS_FILEPATH, CHECKED_IDS_FILEPATH)?;
+  info!(ids = ids_to_check.len(), "checking versions");
+  check_ids_http(ids_to_check, CHECKED_IDS_FILEPATH)?;
+  info!("done");
+  Ok(())
+}
@@ -0,0 +1,64 @@
+/// Migrates the local corpus from one output format to another, e.g. zip to tar.zst,
+/// checking each converted paper against the content fingerprint of the original.
+///
+///   convert_corpus <from> <to> [corpus_root]
+///
+/// Formats are `zip`, `tar.zst`, `tar.gz` and `dir`. Papers already converted are skipped,
+/// so an interrupted run can simply be restarted.
+use std::env;
+use std::error::Error;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use jwalk::WalkDir;
+use rayon::prelude::*;
+use tracing::{error, info};
+
+use ar5iv_util::local::output::{convert_paper, OutputFormat};
+use ar5iv_util::local::CORPUS_ROOT_PATH;
+use ar5iv_util::logging;
+
+const USAGE: &str = "usage: convert_corpus <from> <to> [corpus_root], formats: zip|tar.zst|tar.gz|dir";
+
+fn main() -> Result<(), Box<dyn Error>> {
+  logging::init();
+  let mut args = env::args().skip(1);
+  let from = args.next().and_then(|name| OutputFormat::from_name(&name)).ok_or(USAGE)?;
+  let to = args.next().and_then(|name| OutputFormat::from_name(&name)).ok_or(USAGE)?;
+  let root = args.next().unwrap_or_else(|| CORPUS_ROOT_PATH.to_owned());
+  info!(from = from.name(), to = to.name(), root = %root, "converting corpus");
+
+  let converted = AtomicUsize::new(0);
+  let failed = AtomicUsize::new(0);
+  // paper directories sit at `{root}/{yymm}/{id}`
+  WalkDir::new(&root)
+    .follow_links(true)
+    .max_depth(2)
+    .min_depth(2)
+    .into_iter()
+    .flatten()
+    .filter(|entry| entry.file_type().is_dir())
+    .par_bridge()
+    .for_each(|entry| {
+      let to_dir = entry.path().display().to_string();
+      let base_name = entry.file_name().to_string_lossy().into_owned();
+      match convert_paper(&to_dir, &base_name, from, to) {
+        Ok(Some(_)) => {
+          let done = converted.fetch_add(1, Ordering::Relaxed) + 1;
+          if done.is_multiple_of(1000) {
+            info!(converted = done, "progress");
+          }
+        },
+        Ok(None) => {},
+        Err(e) => {
+          failed.fetch_add(1, Ordering::Relaxed);
+          error!(paper = %base_name, error = %e, "conversion failed");
+        },
+      }
+    });
+  let failed = failed.into_inner();
+  info!(converted = converted.into_inner(), failed, "done");
+  if failed > 0 {
+    return Err(format!("{failed} papers failed to convert").into());
+  }
+  Ok(())
+}
@@ -1,15 +1,28 @@
-/// This is a one-time job to be run at the beginning of a global update,
-/// which walks the local corpus and collects the ids available.
-/// We only update *already available* ids.
-
+/// Walks the local corpus and collects the ids available, to be run at the beginning of a global
+/// update. We only update *already available* ids.
+/// The corpus index from the previous run is brought up to date along the way, and the papers
+/// added, changed or removed since are written to `corpus_delta.json`.
+use ar5iv_util::local::inventory::CORPUS_INDEX_FILEPATH;
 use ar5iv_util::local::{
   create_list_of_ids, CORPUS_ROOT_PATH, UNCHECKED_IDS_FILEPATH,
 };
+use ar5iv_util::logging;
 use std::error::Error;
+use std::fs;
+use tracing::info;
+
+const CORPUS_DELTA_FILEPATH: &str = "corpus_delta.json";
 
 fn main() -> Result<(), Box<dyn Error>> {
-  eprintln!("-- gathering ids from local arXiv corpus directory");
-  create_list_of_ids(CORPUS_ROOT_PATH, UNCHECKED_IDS_FILEPATH)?;
-  eprintln!("-- Done!");
+  logging::init();
+  info!(root = CORPUS_ROOT_PATH, "gathering ids from local arXiv corpus directory");
+  let delta = create_list_of_ids(CORPUS_ROOT_PATH, CORPUS_INDEX_FILEPATH, UNCHECKED_IDS_FILEPATH)?;
+  fs::write(CORPUS_DELTA_FILEPATH, serde_json::to_vec_pretty(&delta)?)?;
+  info!(
+    added = delta.added.len(),
+    changed = delta.changed.len(),
+    removed = delta.removed.len(),
+    "done"
+  );
   Ok(())
 }
@@ -1,15 +1,90 @@
 /// This script is meant to be ran periodically, ideally once every day with an arXiv update
 ///
+/// Invoked as `cron_update daemon [delay_minutes]` it stays resident instead, and runs the
+/// daily update `delay_minutes` (default 60) after each arXiv announcement.
+/// `cron_update next-run [delay_minutes]` prints when the daemon would fire next.
+///
+/// Logs go to stderr, set `AR5IV_LOG_FORMAT=json` for JSON lines.
+/// Set `AR5IV_WEBHOOK_URL` to have a JSON summary POSTed there at the end of every run.
+use std::env;
 use std::error::Error;
 use std::fs::File;
 use std::io::{BufRead, BufReader, Write};
 use std::process::Command;
 use std::str;
-use std::path::Path;
+use std::thread;
+use std::time::Instant;
+
+use chrono::Utc;
+use tracing::{error, info, info_span, warn};
 
+use ar5iv_util::local::{count_corpus_papers, CORPUS_ROOT_PATH};
+use ar5iv_util::error::{io_at, Ar5ivError};
+use ar5iv_util::{logging, metrics};
+use ar5iv_util::notify::{notify_from_env, RunStatus};
 use ar5iv_util::oai::fetch_article_list_since;
+use ar5iv_util::report::RunReport;
+use ar5iv_util::schedule::{AnnouncementSchedule, ARXIV_HOLIDAYS_FILEPATH, NEXT_RUN_FILEPATH};
+
+const DEFAULT_DELAY_MINUTES: i64 = 60;
+const LOG_DIR: &str = "./log";
+const LAST_OAI_UPDATE_FILEPATH: &str = "last_oai_update.txt";
 
 pub fn main() -> Result<(), Box<dyn Error>> {
+  logging::init();
+  let mut args = env::args();
+  let _ = args.next();
+  let mode = args.next();
+  let delay_minutes = match args.next() {
+    Some(minutes) => minutes.parse()?,
+    None => DEFAULT_DELAY_MINUTES,
+  };
+  match mode.as_deref() {
+    Some("daemon") => daemon(delay_minutes),
+    Some("next-run") => {
+      let schedule = AnnouncementSchedule::new(chrono::Duration::minutes(delay_minutes))
+        .with_holidays_file(ARXIV_HOLIDAYS_FILEPATH)?;
+      println!("{}", schedule.next_run_after(Utc::now()).to_rfc3339());
+      Ok(())
+    },
+    _ => run_daily(),
+  }
+}
+
+/// Sleep until the next announcement (plus delay) and run the daily update, forever.
+/// The planned time is also written to `next_run.txt` for outside inspection.
+/// I/O trouble with either file is logged, and the daemon keeps scheduling.
+fn daemon(delay_minutes: i64) -> Result<(), Box<dyn Error>> {
+  let delay = chrono::Duration::minutes(delay_minutes);
+  let mut schedule = AnnouncementSchedule::new(delay);
+  loop {
+    // re-read the holidays each round, so that the list can be extended without a restart.
+    // On failure, keep the holidays known so far.
+    match AnnouncementSchedule::new(delay).with_holidays_file(ARXIV_HOLIDAYS_FILEPATH) {
+      Ok(reloaded) => schedule = reloaded,
+      Err(e) => error!(error = %e, "daemon: could not read the holidays, keeping the last ones"),
+    }
+    let next_run = schedule.next_run_after(Utc::now());
+    if let Err(e) = File::create(NEXT_RUN_FILEPATH)
+      .and_then(|mut next_run_file| writeln!(next_run_file, "{}", next_run.to_rfc3339()))
+    {
+      error!(error = %e, path = NEXT_RUN_FILEPATH, "daemon: could not record the next run");
+    }
+    info!(next_run = %next_run.to_rfc3339(), "daemon: next update planned");
+    if let Ok(wait) = (next_run.with_timezone(&Utc) - Utc::now()).to_std() {
+      thread::sleep(wait);
+    }
+    // a failed day should not bring the daemon down, the next announcement retries the range.
+    if let Err(e) = run_daily() {
+      error!(error = ?e, "daemon: daily update failed");
+    }
+  }
+}
+
+fn run_daily() -> Result<(), Box<dyn Error>> {
+  let start_time = Instant::now();
+  // the daemon runs every day in the same process, and reports each run on its own
+  metrics::reset();
   // Step 0. Now the current date
   // arXiv sits in the TZ="America/New_York" Eastern timezone
   let today_exec = Command::new("date")
@@ -20,27 +95,59 @@ pub fn main() -> Result<(), Box<dyn Error>> {
   let mut today_stdout = today_exec.stdout;
   today_stdout.pop();
   let today =  str::from_utf8(&today_stdout)?;
+  let _span = info_span!("daily", date = today).entered();
+  // adds to what `update_arxiv_sources` may have reported for the day already
+  let mut report = RunReport::load_or_new(LOG_DIR, today).unwrap_or_else(|e| {
+    warn!(error = %e, "could not load today's report, starting a new one");
+    RunReport::new(today)
+  });
+  let result = daily_steps(today, &mut report);
+  if let Err(e) = result.as_ref() {
+    report.record_error(e.kind(), &e.to_string());
+  }
+  // Wrap up. Always leave a report behind, also for failed runs.
+  report.duration_secs = (Instant::now() - start_time).as_secs();
+  let report_written = report.write_to_log_dir(LOG_DIR);
+  metrics::set_corpus_papers(count_corpus_papers(CORPUS_ROOT_PATH));
+  if let Err(e) = metrics::write_textfile("daily", result.is_ok()) {
+    warn!(error = ?e, "failed to write metrics");
+  }
+  // notify last, so that a slow or unreachable webhook never holds up the run's own bookkeeping.
+  notify_from_env(RunStatus::of(&report, result.is_ok()), &report);
+  report_written?;
+  Ok(result?)
+}
+
+fn daily_steps(today: &str, report: &mut RunReport) -> Result<(), Ar5ivError> {
   // Step 1. Obtain the list of all modified articles since last update, via OAI
   // last update is stored in `last_oai_update.txt`
-  let last_oai_update_file = File::open("last_oai_update.txt")?;
+  let last_oai_update_file =
+    File::open(LAST_OAI_UPDATE_FILEPATH).map_err(io_at(LAST_OAI_UPDATE_FILEPATH))?;
   let reader = BufReader::new(last_oai_update_file);
   let last_date = reader.lines().last()
-    .expect("The last line of last_oai_update.txt must contain a date.")
-    .expect("The last line of last_oai_update.txt must contain a date.");
+    .and_then(|line| line.ok())
+    .ok_or_else(|| Ar5ivError::State {
+      path: LAST_OAI_UPDATE_FILEPATH.to_owned(),
+      message: String::from("the last line must contain a date"),
+    })?;
   let mut article_list = fetch_article_list_since(&last_date)?;
-  eprintln!("oai listed {} entries to update.", article_list.len());
+  info!(since = %last_date, entries = article_list.len(), "oai listed entries to update");
+  report.harvested = article_list.len();
+  metrics::record_ids_harvested(article_list.len());
   article_list.sort();
   // 1.1 save in log/ for today.
-  let oai_today_log_path_str = format!("./log/oai_ids_upto_{today}.log");
-  let oai_today_log_path = Path::new(&oai_today_log_path_str);
-  let mut oai_log_file = File::create(oai_today_log_path)?;
-  for article_id in article_list.into_iter() {
-    writeln!(oai_log_file, "{article_id}")?;
+  let oai_today_log_path_str = format!("{LOG_DIR}/oai_ids_upto_{today}.log");
+  let mut oai_log_file =
+    File::create(&oai_today_log_path_str).map_err(io_at(&oai_today_log_path_str))?;
+  for article_id in article_list.iter() {
+    writeln!(oai_log_file, "{article_id}").map_err(io_at(&oai_today_log_path_str))?;
   }
 
   // Step 2. Fetch the sources of all articles that need update.
+  // `update_arxiv_sources` does, and records what it fetched in today's report.
 
   // Step 3. For all successfully fetched articles, update CorTeX tasks to "TODO"
+  // Those are the report's `changed_tex_ids`: unchanged and non-TeX sources need no conversion.
 
   // Step 4. Wrap up. If everything looks nominal, mark today's date as a successful update.
 
@@ -0,0 +1,134 @@
+/// Ingests arXiv's bulk source dumps (the `arXiv_src_YYMM_NNN.tar` bundles) from a local
+/// directory into the corpus, repackaging every paper they hold.
+///
+///   ingest_bulk <bundle_dir> [--only-newer]
+///
+/// Bundles are ingested one at a time, oldest first, so that later bundles win for papers
+/// revised in between; the papers within a bundle are repackaged in parallel. Completed
+/// bundles are logged to `ingested_bundles.txt` and skipped on the next run, so an interrupted
+/// ingest can simply be restarted.
+/// With `--only-newer`, papers whose local sources are at least as recent as the bundled
+/// ones are left alone.
+use std::collections::HashSet;
+use std::env;
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::prelude::*;
+use std::io::BufReader;
+
+use tracing::{error, info};
+
+use ar5iv_util::local::bulk::{
+  bundle_order, ingest_bundle, BundleSummary, INGESTED_BUNDLES_FILEPATH,
+};
+use ar5iv_util::local::encoding;
+use ar5iv_util::local::limits::RepackageLimits;
+use ar5iv_util::local::nested;
+use ar5iv_util::local::output::OutputFormat;
+use ar5iv_util::local::readme;
+use ar5iv_util::local::strip::StripPolicy;
+use ar5iv_util::local::versions::RetentionPolicy;
+use ar5iv_util::local::{count_corpus_papers, RepackageOptions, CORPUS_ROOT_PATH, QUARANTINE_PATH};
+use ar5iv_util::{logging, metrics};
+
+const USAGE: &str = "usage: ingest_bulk <bundle_dir> [--only-newer]";
+
+fn main() -> Result<(), Box<dyn Error>> {
+  logging::init();
+  let result = ingest();
+  metrics::set_corpus_papers(count_corpus_papers(CORPUS_ROOT_PATH));
+  metrics::write_textfile("ingest_bulk", result.is_ok())?;
+  result
+}
+
+fn ingest() -> Result<(), Box<dyn Error>> {
+  let mut bundle_dir = None;
+  let mut only_newer = false;
+  for arg in env::args().skip(1) {
+    match arg.as_str() {
+      "--only-newer" => only_newer = true,
+      _ if bundle_dir.is_none() => bundle_dir = Some(arg),
+      _ => return Err(USAGE.into()),
+    }
+  }
+  let bundle_dir = bundle_dir.ok_or(USAGE)?;
+
+  let ingested: HashSet<String> = match File::open(INGESTED_BUNDLES_FILEPATH) {
+    Ok(file) => BufReader::new(file).lines().map_while(Result::ok).collect(),
+    Err(_) => HashSet::new(),
+  };
+  let mut bundles: Vec<_> = fs::read_dir(&bundle_dir)?
+    .flatten()
+    .map(|entry| entry.path())
+    .filter(|path| {
+      path.file_name().and_then(|name| name.to_str()).is_some_and(|name| {
+        name.starts_with("arXiv_src_") && name.ends_with(".tar") && !ingested.contains(name)
+      })
+    })
+    .collect();
+  // oldest first, so that later bundles win for papers revised in between;
+  // by date rather than name, as the 1990s bundles' names sort after 2000's
+  bundles.sort_by_cached_key(|path| {
+    let name = path.file_name().unwrap().to_string_lossy().into_owned();
+    (bundle_order(&name).unwrap_or((u32::MAX, 0, 0)), name)
+  });
+  info!(bundles = bundles.len(), already_ingested = ingested.len(), only_newer, "ingesting");
+
+  let options = RepackageOptions {
+    retention: RetentionPolicy::from_env(),
+    quarantine_dir: Some(QUARANTINE_PATH.to_owned()),
+    expand_nested_depth: nested::depth_from_env(),
+    deterministic: true,
+    format: OutputFormat::from_env(),
+    strip: StripPolicy::from_env(),
+    drop_ignored: readme::drop_ignored_from_env(),
+    limits: RepackageLimits::from_env(),
+    normalize_encoding: encoding::normalize_from_env(),
+    ..RepackageOptions::default()
+  };
+  let mut ingested_log = File::options()
+    .create(true)
+    .append(true)
+    .open(INGESTED_BUNDLES_FILEPATH)?;
+  let mut summaries: Vec<BundleSummary> = Vec::new();
+  for bundle in bundles.iter() {
+    match ingest_bundle(bundle, &options, only_newer) {
+      Ok(summary) => {
+        info!(
+          bundle = %summary.bundle,
+          members = summary.members,
+          repackaged = summary.repackaged,
+          skipped = summary.skipped,
+          failed = summary.failed,
+          "bundle ingested"
+        );
+        let name = bundle.file_name().unwrap().to_string_lossy();
+        if let Err(e) = writeln!(ingested_log, "{name}") {
+          error!(bundle = %name, error = %e, "could not log ingested bundle");
+        }
+        summaries.push(summary);
+      },
+      Err(e) => {
+        // not logged as ingested, so the next run retries it
+        error!(bundle = %bundle.display(), error = %e, "bundle ingest failed");
+        if e.is_fatal() {
+          break;
+        }
+      },
+    }
+  }
+
+  let incomplete = bundles.len() - summaries.len();
+  info!(
+    bundles = summaries.len(),
+    repackaged = summaries.iter().map(|s| s.repackaged).sum::<usize>(),
+    skipped = summaries.iter().map(|s| s.skipped).sum::<usize>(),
+    failed = summaries.iter().map(|s| s.failed).sum::<usize>(),
+    incomplete,
+    "done"
+  );
+  if incomplete > 0 {
+    return Err(format!("{incomplete} bundles could not be ingested").into());
+  }
+  Ok(())
+}
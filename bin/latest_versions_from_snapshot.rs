@@ -6,6 +6,10 @@ use std::io::BufReader;
 use std::path::Path;
 use chrono::{DateTime, TimeZone, FixedOffset};
 use once_cell::sync::Lazy;
+use tracing::info;
+
+use ar5iv_util::error::Ar5ivError;
+use ar5iv_util::logging;
 
 // When was the last date a full update was ran?
 pub static LAST_UPDATE : Lazy<DateTime<FixedOffset>> = Lazy::new(||
@@ -13,6 +17,7 @@ pub static LAST_UPDATE : Lazy<DateTime<FixedOffset>> = Lazy::new(||
 );
 
 fn main() -> Result<(), Box<dyn Error>> {
+  logging::init();
   // JSON obtained from:
   // https://www.kaggle.com/datasets/1b6883fb66c5e7f67c697c2547022cc04c9ee98c3742f9a4d6c671b4f4eda591?resource=download&select=arxiv-metadata-oai-snapshot.json
   let snapshot_path = Path::new("arxiv-metadata-oai-snapshot.json");
@@ -25,24 +30,32 @@ fn main() -> Result<(), Box<dyn Error>> {
 
   for value_result in stream {
     let value = value_result?;
-    let versions = value.get("versions").unwrap().as_array().unwrap();
+    let versions = value
+      .get("versions")
+      .and_then(Value::as_array)
+      .ok_or_else(|| Ar5ivError::Parse(format!("entry without a versions array: {value}")))?;
     if versions.len() < 2 {
       continue; // skip single version cases, we already have them.
     }
     for val in versions {
-      let created : DateTime<FixedOffset> = DateTime::parse_from_rfc2822(
-      val.get("created").unwrap().as_str().unwrap())?;
+      let created_str = val
+        .get("created")
+        .and_then(Value::as_str)
+        .ok_or_else(|| Ar5ivError::Parse(format!("version without a created date: {val}")))?;
+      let created : DateTime<FixedOffset> = DateTime::parse_from_rfc2822(created_str)?;
       if *LAST_UPDATE < created {
         total_gathered += 1;
-        let value_str = value.get("id").unwrap().as_str().unwrap();
+        let value_str = value
+          .get("id")
+          .and_then(Value::as_str)
+          .ok_or_else(|| Ar5ivError::Parse(format!("entry without an id: {value}")))?;
         writeln!(gather_file, "{value_str}")?;
         break;
       }
     }
   }
 
-  eprintln!(
-    "-- gathered {total_gathered} aritcle ids with version 2 or up.");
+  info!(total_gathered, "gathered article ids with version 2 or up");
 
   Ok(())
 }
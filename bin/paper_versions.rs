@@ -0,0 +1,40 @@
+/// Lists and restores the earlier source versions kept for a paper, see `AR5IV_KEEP_VERSIONS`.
+///
+///   paper_versions list <arxiv_id>
+///   paper_versions restore <arxiv_id> <version>
+use std::env;
+use std::error::Error;
+
+use ar5iv_util::local::corpus_paths;
+use ar5iv_util::local::versions::{current_version, list_versions, restore_version};
+use ar5iv_util::logging;
+use tracing::info;
+
+const USAGE: &str = "usage: paper_versions list <arxiv_id> | paper_versions restore <arxiv_id> <version>";
+
+fn main() -> Result<(), Box<dyn Error>> {
+  logging::init();
+  let mut args = env::args();
+  let _ = args.next();
+  let command = args.next().ok_or(USAGE)?;
+  let arxiv_id = args.next().ok_or(USAGE)?;
+  let (to_dir, base_name) = corpus_paths(&arxiv_id)?;
+  match command.as_str() {
+    "list" => {
+      match current_version(&to_dir, &base_name) {
+        Some(version) => println!("current\tv{version}\t{to_dir}/{base_name}.zip"),
+        None => println!("current\tv?\t{to_dir}/{base_name}.zip"),
+      }
+      for archived in list_versions(&to_dir, &base_name)? {
+        println!("archived\tv{}\t{}", archived.version, archived.path.display());
+      }
+    },
+    "restore" => {
+      let version = args.next().ok_or(USAGE)?.trim_start_matches('v').parse()?;
+      let restored = restore_version(&to_dir, &base_name, version)?;
+      info!(paper = %arxiv_id, version, from = %restored.path.display(), "restored");
+    },
+    _ => return Err(USAGE.into()),
+  }
+  Ok(())
+}
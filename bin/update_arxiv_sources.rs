@@ -1,5 +1,5 @@
 #![feature(iter_array_chunks)]
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::env;
 use std::error::Error;
 use std::fs::File;
@@ -9,19 +9,60 @@ use std::io::BufReader;
 use std::time::{Instant, Duration};
 use std::path::Path;
 
-use regex::Regex;
 use reqwest::blocking::Client;
 use rayon::prelude::*;
+use serde_json::json;
+use tracing::{info, warn};
 
-use ar5iv_util::local::{CORPUS_ROOT_PATH, IDS_TO_UPDATE_FILEPATH};//UNCHECKED_IDS_FILEPATH
-use ar5iv_util::local::repackage_arxiv_download;
+use ar5iv_util::local::{
+  ALREADY_UPDATED_FILEPATH, CHECKED_IDS_FILEPATH, CORPUS_ROOT_PATH, IDS_TO_UPDATE_FILEPATH,
+  NON_TEX_IDS_FILEPATH, QUARANTINE_PATH,
+};//UNCHECKED_IDS_FILEPATH
+use ar5iv_util::local::{
+  corpus_paths, count_corpus_papers, record_non_tex, repackage_eprint, EPrintSource,
+  RepackageOptions, RepackageReport,
+};
+use ar5iv_util::local::encoding;
+use ar5iv_util::local::limits::RepackageLimits;
+use ar5iv_util::local::nested;
+use ar5iv_util::local::output::OutputFormat;
+use ar5iv_util::local::readme;
+use ar5iv_util::local::strip::StripPolicy;
+use ar5iv_util::local::versions::{checked_versions, version_from_filename, RetentionPolicy};
+use ar5iv_util::{logging, metrics};
+use ar5iv_util::error::{io_at, Ar5ivError};
+use ar5iv_util::remote::{fetch_eprint, DownloadOutcome, EPrint, Spool};
+use ar5iv_util::report::{eastern_today, RunReport};
 
 const NUM_THREADS : usize = 4;
-const RESUME_LOG_FILEPATH : &str = "already_updated.log";
+const REPACKAGE_REPORTS_FILEPATH : &str = "repackage_reports.jsonl";
+const LOG_DIR: &str = "./log";
 
 fn main() -> Result<(), Box<dyn Error>> {
+  logging::init();
+  let result = update_sources();
+  metrics::set_corpus_papers(count_corpus_papers(CORPUS_ROOT_PATH));
+  metrics::write_textfile("fetch", result.is_ok())?;
+  result
+}
+
+fn update_sources() -> Result<(), Box<dyn Error>> {
   let start_time = Instant::now();
-  let retry_indexes: [i32;3] = [0,1,2];
+  // what was fetched goes into the day's run report, next to what `cron_update` harvested
+  let today = eastern_today();
+  let mut run_report = RunReport::load_or_new(LOG_DIR, &today).unwrap_or_else(|e| {
+    warn!(error = %e, "could not load today's report, starting a new one");
+    RunReport::new(&today)
+  });
+  let result = fetch_sources(start_time, &mut run_report);
+  if let Err(e) = result.as_ref() {
+    run_report.record_error(e.kind(), &e.to_string());
+  }
+  run_report.write_to_log_dir(LOG_DIR)?;
+  Ok(result?)
+}
+
+fn fetch_sources(start_time: Instant, run_report: &mut RunReport) -> Result<(), Ar5ivError> {
   let mut args = env::args();
   let _ = args.next();
   let ids_to_update_path = args
@@ -36,26 +77,48 @@ fn main() -> Result<(), Box<dyn Error>> {
   // load the Set of local ids we have available
   // let all_local_ids = build_set(&unchecked_ids_path);
   // load the Set of already covered ids
-  let already_updated = build_set(RESUME_LOG_FILEPATH);
+  let already_updated = build_set(ALREADY_UPDATED_FILEPATH);
   // save newly updated files to allow easy resume.
-  let mut resume_file = if Path::new(RESUME_LOG_FILEPATH).exists() {
-    File::options().append(true).open(RESUME_LOG_FILEPATH)?
+  let mut resume_file = if Path::new(ALREADY_UPDATED_FILEPATH).exists() {
+    File::options().append(true).open(ALREADY_UPDATED_FILEPATH)
   } else {
-    File::create(RESUME_LOG_FILEPATH)?
-  };
+    File::create(ALREADY_UPDATED_FILEPATH)
+  }
+  .map_err(io_at(ALREADY_UPDATED_FILEPATH))?;
+  // keep what became of every repackaged download, one JSON object per line.
+  let mut report_file = File::options()
+    .create(true)
+    .append(true)
+    .open(REPACKAGE_REPORTS_FILEPATH)
+    .map_err(io_at(REPACKAGE_REPORTS_FILEPATH))?;
   // cover the intersection
   // let mut ids_to_update = all_ids_to_update.into_iter().filter(|e| all_local_ids.contains(e) && !already_updated.contains(e));
   // recovery for 2308, also download fresh entries:
   let mut ids_to_update = all_ids_to_update.into_iter()
     .filter(|e| !already_updated.contains(e));
 
-  let slash_regex = Regex::new("^([^/]+)/([^/]+)$").unwrap();
-
   // reuse a group of download Clients
   let clients : Vec<Client> = (0..NUM_THREADS).map(|_| reqwest::blocking::Client::builder()
     .user_agent("ar5iv (https://ar5iv.labs.arxiv.org)")
     .timeout(Duration::from_secs(120))
     .build().unwrap()).collect();
+  // keep replaced versions around, if so configured via AR5IV_KEEP_VERSIONS
+  let options = RepackageOptions {
+    retention: RetentionPolicy::from_env(),
+    quarantine_dir: Some(QUARANTINE_PATH.to_owned()),
+    expand_nested_depth: nested::depth_from_env(),
+    deterministic: true,
+    format: OutputFormat::from_env(),
+    strip: StripPolicy::from_env(),
+    drop_ignored: readme::drop_ignored_from_env(),
+    limits: RepackageLimits::from_env(),
+    normalize_encoding: encoding::normalize_from_env(),
+    ..RepackageOptions::default()
+  };
+  // to number the versions of the sources, where the served file name does not tell
+  let checked_versions = checked_versions(CHECKED_IDS_FILEPATH)?;
+  // e-prints are streamed to disk, at most AR5IV_MAX_EPRINT_BYTES each
+  let spool = Spool::from_env();
   let mut updated = 0;
   while let Some(batch_id) = ids_to_update.next() {
     let mut batch = vec![(batch_id, &clients[0])];
@@ -64,49 +127,52 @@ fn main() -> Result<(), Box<dyn Error>> {
         batch.push((nid, this_client));
       }
     }
-    let _downloaded_ok: Vec<bool> = batch.par_iter().map(|(id, client)| {
-      // the URL we download from
-      let url_owned = format!("https://export.arxiv.org/e-print/{id}");
-      let url = &url_owned;
-      'retries: for _retry in &retry_indexes {
-        if let Ok(payload) = client.get(url).send() {
-          match payload.status().as_u16() {
-            200 => match payload.bytes() {
-              Ok(bytes) => {
-                // only execute if we get some bytes
-                if !bytes.is_empty() {
-                  let (to_dir,base_name) = if let Some(cap) = slash_regex.captures(id) {
-                    let base = cap.get(1).unwrap().as_str();
-                    let id = cap.get(2).unwrap().as_str();
-                    let mmyy = &id[..4];
-                    (format!("{CORPUS_ROOT_PATH}/{mmyy}/{base}{id}"),
-                    format!("{base}{id}"))
-                  } else {
-                    let mmyy = &id[..4];
-                    (format!("{CORPUS_ROOT_PATH}/{mmyy}/{id}"),
-                    id.to_owned())
-                  };
-                  repackage_arxiv_download(&mut bytes.to_vec(), to_dir, base_name);
-                  break 'retries;
-                } else {
-                  eprintln!("Code 200 but no bytes returned; article id {id}.");
-                }
-              },
-              Err(e) => {
-                eprintln!("Code 200 but bytes returned had error {e:?}; article id {id}.");
-              }
-            },
-            403 => {eprintln!("code 403 for article id {id}, skip."); break 'retries;},
-            other => eprintln!("code {other} for article id {id}."),
-          }
-        }
-      }
-      true
+    let repackaged: Vec<Fetched> = batch.par_iter().map(|(id, client)| {
+      let mut outcome = fetch_eprint(client, &spool, id);
+      // only repackage if we got some bytes
+      let repackaged = match &mut outcome {
+        DownloadOutcome::Downloaded(eprint) => {
+          repackage_download(id, eprint, &options, &checked_versions).map(Some)
+        },
+        _ => Ok(None),
+      };
+      Fetched { outcome, repackaged }
     }).collect();
+    let mut fatal = None;
+    for ((id, _), fetched) in batch.iter().zip(repackaged) {
+      run_report.record_download(id, &fetched.outcome);
+      let line = match fetched.repackaged {
+        Ok(None) => continue,
+        Ok(Some((report, is_new))) => {
+          run_report.record_repackaged(id, is_new, &report);
+          // PDF-only and HTML submissions are kept, but not sent to conversion
+          if !report.class.is_tex() && !report.unchanged {
+            record_non_tex(id, report.class, NON_TEX_IDS_FILEPATH)?;
+          }
+          json!({"id": id, "report": report})
+        },
+        Err(e) => {
+          let line = json!({"id": id, "error": e.to_string()});
+          if e.is_fatal() {
+            // counted once the run ends on it
+            fatal = fatal.or(Some(e));
+          } else {
+            run_report.record_repackage_failure(e.kind(), &e.to_string());
+          }
+          line
+        },
+      };
+      writeln!(report_file, "{line}").map_err(io_at(REPACKAGE_REPORTS_FILEPATH))?;
+    }
+    // Failures for a single paper are logged and skipped, but a failing disk ends the run,
+    // before the batch is marked as done.
+    if let Some(e) = fatal {
+      return Err(e);
+    }
     updated += batch.len();
     if updated % 100 == 0 {
-      eprintln!("-- updated {} articles in {} sec...", updated, (Instant::now()-start_time).as_secs());
-      dbg!(&batch);
+      let ids: Vec<&String> = batch.iter().map(|(id, _)| id).collect();
+      info!(updated, secs = (Instant::now()-start_time).as_secs(), batch = ?ids, "progress");
     }
     // Update: if we get a 403, it is almost always per author's request
     // so skip them as done.
@@ -115,16 +181,46 @@ fn main() -> Result<(), Box<dyn Error>> {
     // // see for example the author-requested 403 here: https://export.arxiv.org/e-print/math/0607467
     // if downloaded_ok.into_iter().all(|a| a) {
     for (id, _) in batch {
-      writeln!(resume_file, "{id}")?;
+      writeln!(resume_file, "{id}").map_err(io_at(ALREADY_UPDATED_FILEPATH))?;
     }
     // }
     // courtesy sleep for reducing the load on arXiv's infra.
     // thread::sleep(Duration::from_secs(1));
   }
-  eprintln!("-- Done: updated {} articles in {} sec.", updated, (Instant::now()-start_time).as_secs());
+  info!(updated, secs = (Instant::now()-start_time).as_secs(), "done");
   Ok(())
 }
 
+/// A download, and what became of it.
+struct Fetched {
+  outcome: DownloadOutcome,
+  /// The repackaging report, and whether the paper is new to the corpus
+  repackaged: Result<Option<(RepackageReport, bool)>, Ar5ivError>,
+}
+
+fn repackage_download(
+  id: &str,
+  eprint: &mut EPrint,
+  options: &RepackageOptions,
+  checked_versions: &HashMap<String, usize>,
+) -> Result<(RepackageReport, bool), Ar5ivError> {
+  let (to_dir, base_name) = corpus_paths(id)?;
+  let is_new = !Path::new(&to_dir).exists();
+  // the served file name is the surest, the last version check may be outdated
+  let version = eprint
+    .filename
+    .as_deref()
+    .and_then(version_from_filename)
+    .or_else(|| checked_versions.get(id).copied());
+  let options = RepackageOptions {
+    version,
+    filename_hint: eprint.filename.take(),
+    ..options.clone()
+  };
+  let source = EPrintSource::File(&eprint.path);
+  Ok((repackage_eprint(source, to_dir, base_name, &options)?, is_new))
+}
+
 fn build_set(path: &str) -> HashSet<String> {
   if let Ok(file) = File::open(path) {
     let reader = BufReader::new(file);
@@ -0,0 +1,119 @@
+/// Checks the integrity of the local corpus: every paper directory should hold a readable
+/// output, whose entries read through, with at least one TeX source and the fingerprint it was
+/// written with.
+///
+///   verify_corpus [corpus_root]
+///
+/// Papers with problems are written to `verify_report.jsonl`, and those a new download should
+/// fix are queued in `ids_to_update.txt`, for `update_arxiv_sources` to pick up. They are taken
+/// out of its `already_updated.log` as well, or it would skip them as done.
+use std::collections::HashSet;
+use std::env;
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::prelude::*;
+use std::io::{self, BufReader};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+use jwalk::WalkDir;
+use rayon::prelude::*;
+use serde_json::json;
+use tracing::{error, info, warn};
+
+use ar5iv_util::local::verify::verify_paper;
+use ar5iv_util::local::{ALREADY_UPDATED_FILEPATH, CORPUS_ROOT_PATH, IDS_TO_UPDATE_FILEPATH};
+use ar5iv_util::logging;
+
+const VERIFY_REPORT_FILEPATH: &str = "verify_report.jsonl";
+
+fn main() -> Result<(), Box<dyn Error>> {
+  logging::init();
+  let root = env::args()
+    .nth(1)
+    .unwrap_or_else(|| CORPUS_ROOT_PATH.to_owned());
+  info!(root = %root, "verifying corpus");
+
+  let report_file = Mutex::new(File::create(VERIFY_REPORT_FILEPATH)?);
+  let to_refetch = Mutex::new(Vec::new());
+  let checked = AtomicUsize::new(0);
+  let broken = AtomicUsize::new(0);
+  // paper directories sit at `{root}/{yymm}/{id}`
+  WalkDir::new(&root)
+    .follow_links(true)
+    .max_depth(2)
+    .min_depth(2)
+    .into_iter()
+    .flatten()
+    .filter(|entry| entry.file_type().is_dir())
+    .par_bridge()
+    .for_each(|entry| {
+      let to_dir = entry.path().display().to_string();
+      let base_name = entry.file_name().to_string_lossy().into_owned();
+      let check = verify_paper(&to_dir, &base_name);
+      let done = checked.fetch_add(1, Ordering::Relaxed) + 1;
+      if done.is_multiple_of(10_000) {
+        info!(checked = done, "progress");
+      }
+      if check.is_ok() {
+        return;
+      }
+      broken.fetch_add(1, Ordering::Relaxed);
+      warn!(paper = %check.id, problems = check.problems.len(), "paper has problems");
+      if check.needs_refetch() {
+        to_refetch.lock().unwrap().push(check.id.clone());
+      }
+      let line = json!({"id": check.id, "report": check});
+      if let Err(e) = writeln!(report_file.lock().unwrap(), "{line}") {
+        error!(paper = %check.id, error = %e, "could not write to the report");
+      }
+    });
+
+  // queue the papers to download again, once each
+  let queued: HashSet<String> = match File::open(IDS_TO_UPDATE_FILEPATH) {
+    Ok(file) => BufReader::new(file).lines().map_while(Result::ok).collect(),
+    Err(_) => HashSet::new(),
+  };
+  let mut to_refetch = to_refetch.into_inner().unwrap();
+  to_refetch.sort();
+  forget_updated(&to_refetch)?;
+  to_refetch.retain(|id| !queued.contains(id));
+  let mut queue_file = File::options()
+    .create(true)
+    .append(true)
+    .open(IDS_TO_UPDATE_FILEPATH)?;
+  for id in to_refetch.iter() {
+    writeln!(queue_file, "{id}")?;
+  }
+  info!(
+    checked = checked.into_inner(),
+    broken = broken.into_inner(),
+    queued = to_refetch.len(),
+    report = VERIFY_REPORT_FILEPATH,
+    "done"
+  );
+  Ok(())
+}
+
+/// Take `ids` out of the resume log of `update_arxiv_sources`, so that it downloads them again.
+fn forget_updated(ids: &[String]) -> Result<(), Box<dyn Error>> {
+  let updated = match fs::read_to_string(ALREADY_UPDATED_FILEPATH) {
+    Ok(updated) => updated,
+    Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+    Err(e) => return Err(e.into()),
+  };
+  let ids: HashSet<&str> = ids.iter().map(String::as_str).collect();
+  let kept: Vec<&str> = updated.lines().filter(|id| !ids.contains(id)).collect();
+  if kept.len() == updated.lines().count() {
+    return Ok(());
+  }
+  // replaced in one go, so that an interrupted run leaves the log as it was
+  let tmp_filepath = format!("{ALREADY_UPDATED_FILEPATH}.tmp");
+  let mut tmp_file = File::create(&tmp_filepath)?;
+  for id in kept {
+    writeln!(tmp_file, "{id}")?;
+  }
+  tmp_file.sync_all()?;
+  fs::rename(&tmp_filepath, ALREADY_UPDATED_FILEPATH)?;
+  Ok(())
+}
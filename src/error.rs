@@ -0,0 +1,98 @@
+//! The error type shared by all of ar5iv-util's public functions.
+use std::io;
+use std::path::Path;
+
+use thiserror::Error;
+
+pub type Result<T, E = Ar5ivError> = std::result::Result<T, E>;
+
+#[derive(Debug, Error)]
+pub enum Ar5ivError {
+  /// The request never got a response: connection, timeout, or body transfer failures.
+  #[error("network error for {url}: {source}")]
+  Network {
+    url: String,
+    #[source]
+    source: reqwest::Error,
+  },
+  #[error("HTTP {status} from {url}")]
+  HttpStatus { url: String, status: u16 },
+  /// A malformed OAI-PMH response, or an OAI-PMH `<error>` other than `noRecordsMatch`.
+  #[error("OAI-PMH protocol error: {0}")]
+  Oai(String),
+  #[error("archive error for {path}: {message}")]
+  Archive { path: String, message: String },
+  /// Filesystem I/O on the corpus, or on the job's own lists and logs.
+  #[error("I/O error at {path}: {source}")]
+  CorpusIo {
+    path: String,
+    #[source]
+    source: io::Error,
+  },
+  /// A job state file (last update date, id lists, ...) is missing required content.
+  #[error("invalid state in {path}: {message}")]
+  State { path: String, message: String },
+  #[error("parse error: {0}")]
+  Parse(String),
+}
+
+impl Ar5ivError {
+  /// Worth another attempt later: network trouble and arXiv-side 5xx/429 responses.
+  pub fn is_retryable(&self) -> bool {
+    match self {
+      Ar5ivError::Network { .. } => true,
+      Ar5ivError::HttpStatus { status, .. } => *status == 429 || *status >= 500,
+      _ => false,
+    }
+  }
+
+  /// No point in continuing the batch: we are banned from arXiv (HTTP 403 on export),
+  /// or the local disk misbehaves.
+  pub fn is_fatal(&self) -> bool {
+    matches!(
+      self,
+      Ar5ivError::HttpStatus { status: 403, .. } | Ar5ivError::CorpusIo { .. }
+    )
+  }
+
+  /// What went wrong, without the particulars (urls, paths), to count errors by.
+  pub fn kind(&self) -> &'static str {
+    match self {
+      Ar5ivError::Network { .. } => "network",
+      Ar5ivError::HttpStatus { .. } => "http status",
+      Ar5ivError::Oai(_) => "oai-pmh",
+      Ar5ivError::Archive { .. } => "archive",
+      Ar5ivError::CorpusIo { .. } => "i/o",
+      Ar5ivError::State { .. } => "job state",
+      Ar5ivError::Parse(_) => "parse",
+    }
+  }
+
+  pub fn archive(path: impl AsRef<Path>, message: impl ToString) -> Self {
+    Ar5ivError::Archive {
+      path: path.as_ref().display().to_string(),
+      message: message.to_string(),
+    }
+  }
+}
+
+/// For `map_err`, attaching the path an I/O error occurred at.
+pub fn io_at(path: impl AsRef<Path>) -> impl FnOnce(io::Error) -> Ar5ivError {
+  let path = path.as_ref().display().to_string();
+  move |source| Ar5ivError::CorpusIo { path, source }
+}
+
+impl From<reqwest::Error> for Ar5ivError {
+  fn from(source: reqwest::Error) -> Self {
+    let url = source.url().map(|url| url.to_string()).unwrap_or_default();
+    Ar5ivError::Network { url, source }
+  }
+}
+
+impl From<serde_json::Error> for Ar5ivError {
+  fn from(e: serde_json::Error) -> Self { Ar5ivError::Parse(e.to_string()) }
+}
+
+impl From<chrono::ParseError> for Ar5ivError {
+  fn from(e: chrono::ParseError) -> Self { Ar5ivError::Parse(e.to_string()) }
+}
@@ -1,3 +1,9 @@
+pub mod error;
 pub mod local;
+pub mod logging;
+pub mod metrics;
 pub mod remote;
+pub mod notify;
 pub mod oai;
+pub mod report;
+pub mod schedule;
@@ -1,80 +1,154 @@
-use std::collections::HashSet;
-use std::error::Error;
+use std::collections::{HashMap, HashSet};
 use std::fs::{self,File};
-use std::io::{prelude::*, BufReader};
-use std::path::Path;
+use std::io::{self, prelude::*, BufReader, BufWriter};
+use std::path::{Path, PathBuf};
 
 use lazy_static::lazy_static;
 use regex::Regex;
-use Archive::*;
 use jwalk::WalkDir;
+use serde::Serialize;
+use thiserror::Error;
+use tracing::{error, info_span, warn};
+
+use crate::error::{io_at, Ar5ivError, Result};
+use crate::metrics;
+
+pub mod archive;
+pub mod bulk;
+pub mod classify;
+pub mod encoding;
+pub mod fingerprint;
+pub mod inventory;
+pub mod limits;
+pub mod manifest;
+pub mod nested;
+pub mod output;
+pub mod readme;
+pub mod sanitize;
+pub mod strip;
+pub mod verify;
+pub mod versions;
+use archive::{backend, ArchiveReader, ReadMode};
+use classify::{classify_members, classify_single_file, SubmissionClass};
+use encoding::{detect_encoding, normalize_to_utf8, TextEncoding, ORIGINAL_SUFFIX};
+use fingerprint::{content_hash, record_hash, stored_hash};
+use inventory::{index_key_id, scan_corpus, ScanDelta};
+use manifest::{build_manifest, record_manifest, single_file_manifest, FileRole, Manifest};
+use nested::expand_nested_archives;
+use output::{install, remove_output, OutputFormat, OutputSink};
+use readme::{find_directives, SubmissionDirectives, DIRECTIVES_ENTRY};
+use limits::{quarantine, LimitExceeded, LimitTracker, RepackageLimits};
+use sanitize::{normalize_entry_path, resolve_link_target, SanitizedEntry};
+use strip::StripPolicy;
+use versions::{
+  discard_retained, prune_versions, record_version, retain_current, RetentionPolicy,
+};
 
 pub const UNCHECKED_IDS_FILEPATH: &str = "unchecked_ids.txt";
 pub const IDS_TO_UPDATE_FILEPATH: &str = "ids_to_update.txt";
+/// Ids `update_arxiv_sources` is done with, skipped when it resumes
+pub const ALREADY_UPDATED_FILEPATH: &str = "already_updated.log";
 pub const CHECKED_IDS_FILEPATH: &str = "checked_ids.csv";
+/// Papers whose sources are not TeX, as `{id},{class}` lines, kept out of conversion
+pub const NON_TEX_IDS_FILEPATH: &str = "non_tex_ids.txt";
 pub const CORPUS_ROOT_PATH: &str = "/data/arxmliv";
+/// Where over-limit payloads are set aside, outside of the corpus
+pub const QUARANTINE_PATH: &str = "/data/arxmliv_quarantine";
 
 const BUFFER_SIZE: usize = 10_240;
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
 
 lazy_static! {
   static ref LETTER_DIGIT_REGEX: Regex = Regex::new("(^\\D+)(\\d.+)$").unwrap();
+  static ref SLASH_REGEX: Regex = Regex::new("^([^/]+)/([^/]+)$").unwrap();
+}
+
+/// The corpus directory and base file name for an arXiv id,
+/// e.g. `hep-th/9901001` maps to (`{CORPUS_ROOT_PATH}/9901/hep-th9901001`, `hep-th9901001`)
+pub fn corpus_paths(arxiv_id: &str) -> Result<(String, String)> {
+  let (base, id) = match SLASH_REGEX.captures(arxiv_id) {
+    Some(cap) => (cap.get(1).unwrap().as_str(), cap.get(2).unwrap().as_str()),
+    None => ("", arxiv_id),
+  };
+  let mmyy = id
+    .get(..4)
+    .filter(|mmyy| mmyy.bytes().all(|b| b.is_ascii_digit()))
+    .ok_or_else(|| Ar5ivError::Parse(format!("not an arXiv id: {arxiv_id:?}")))?;
+  Ok((
+    format!("{CORPUS_ROOT_PATH}/{mmyy}/{base}{id}"),
+    format!("{base}{id}"),
+  ))
 }
 
-pub fn create_list_of_ids(root_path: &str, unchecked_filepath: &str) -> Result<(), Box<dyn Error>> {
-  // only do this once, i.e. if the file exists - skip.
-  let unchecked_path = Path::new(unchecked_filepath);
-  if unchecked_path.exists() {
-    return Ok(());
+/// The arXiv id of a corpus paper, from its base name: the inverse of `corpus_paths`,
+/// e.g. `astro-ph/0001001` for `astro-ph0001001`.
+pub fn arxiv_id_of(base_name: &str) -> String {
+  match LETTER_DIGIT_REGEX.captures(base_name) {
+    Some(cap) => format!("{}/{}", cap.get(1).unwrap().as_str(), cap.get(2).unwrap().as_str()),
+    None => base_name.to_owned(),
   }
+}
 
-  let mut unchecked_file = File::create(unchecked_filepath)?;
+/// Bring the corpus index at `index_filepath` up to date, and write the ids of all local papers
+/// to `unchecked_filepath`. Returns the papers added, changed or removed since the last scan.
+pub fn create_list_of_ids(
+  root_path: &str,
+  index_filepath: &str,
+  unchecked_filepath: &str,
+) -> Result<ScanDelta> {
+  let (index, delta) = scan_corpus(root_path, index_filepath)?;
+  let tmp_filepath = format!("{unchecked_filepath}.tmp");
+  let mut unchecked_file =
+    BufWriter::new(File::create(&tmp_filepath).map_err(io_at(&tmp_filepath))?);
+  for key in index.keys() {
+    writeln!(unchecked_file, "{}", index_key_id(key)).map_err(io_at(&tmp_filepath))?;
+  }
+  unchecked_file.flush().map_err(io_at(&tmp_filepath))?;
+  fs::rename(&tmp_filepath, unchecked_filepath).map_err(io_at(unchecked_filepath))?;
+  Ok(delta)
+}
 
-  for entry in WalkDir::new(root_path)
+/// Count the paper directories in the corpus, which sit at `{root}/{yymm}/{id}`.
+pub fn count_corpus_papers(root_path: &str) -> usize {
+  WalkDir::new(root_path)
     .follow_links(true)
-    .sort(true)
     .max_depth(2)
     .min_depth(2)
     .into_iter()
     .flatten()
-  {
-    let id = entry.file_name().to_str().unwrap();
-    if let Some(cap) = LETTER_DIGIT_REGEX.captures(id) {
-      writeln!(
-        unchecked_file,
-        "{}/{}",
-        cap.get(1).unwrap().as_str(),
-        cap.get(2).unwrap().as_str()
-      )?;
-    } else {
-      writeln!(unchecked_file, "{id}")?;
-    }
-  }
-  Ok(())
+    .filter(|entry| entry.file_type().is_dir())
+    .count()
 }
 
 pub fn filter_list_to_check(
   unchecked_filepath: &str,
   checked_filepath: &str,
-) -> Result<Vec<String>, Box<dyn Error>> {
+) -> Result<Vec<String>> {
   // create a HashSet of the ids already checked
   let checked_path = Path::new(checked_filepath);
   let checked_set = if !checked_path.exists() {
     HashSet::new()
   } else {
-    let checked_file = File::options().read(true).open(checked_filepath)?;
+    let checked_file = File::options()
+      .read(true)
+      .open(checked_filepath)
+      .map_err(io_at(checked_filepath))?;
     let reader = BufReader::new(checked_file);
     let mut set = HashSet::new();
     for line in reader
       .lines()
-      .map_while(Result::ok)
-      .map(|l| l.split(',').next().unwrap().to_owned())
+      .map_while(std::result::Result::ok)
+      .map(|l| l.split(',').next().unwrap_or_default().to_owned())
     {
       set.insert(line);
     }
     set
   };
   // load the uncecked ids and avoid checking them twice.
-  let unchecked_file = File::options().read(true).open(unchecked_filepath)?;
+  let unchecked_file = File::options()
+    .read(true)
+    .open(unchecked_filepath)
+    .map_err(io_at(unchecked_filepath))?;
   let reader = BufReader::new(unchecked_file);
 
   let list_to_check = reader
@@ -85,48 +159,456 @@ pub fn filter_list_to_check(
   Ok(list_to_check)
 }
 
-pub fn repackage_arxiv_download(memory: &mut [u8], to_dir: String, base_name: String) {
-  let default_tex_target = base_name.to_string() + ".tex";
-  fs::create_dir_all(&to_dir).unwrap_or_else(|reason| {
-    println!(
-      "Failed to mkdir -p {:?} because: {:?}",
-      to_dir.clone(),
-      reason.kind()
-    );
+/// Append `{arxiv_id},{class}` to the list of papers kept out of conversion.
+pub fn record_non_tex(arxiv_id: &str, class: SubmissionClass, non_tex_filepath: &str) -> Result<()> {
+  let mut non_tex_file = File::options()
+    .create(true)
+    .append(true)
+    .open(non_tex_filepath)
+    .map_err(io_at(non_tex_filepath))?;
+  writeln!(non_tex_file, "{arxiv_id},{}", class.as_str()).map_err(io_at(non_tex_filepath))
+}
+
+/// The shape of an e-print payload, as detected while repackaging.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum InputKind {
+  /// A (usually gzipped) tar of the submission's files
+  Tar,
+  /// A single gzipped file, typically a lone .tex source
+  GzipSingleFile,
+  /// An uncompressed single file
+  Raw,
+}
+
+/// What `repackage_arxiv_download` wrote, for callers to persist and act on.
+#[derive(Debug, Clone, Serialize)]
+pub struct RepackageReport {
+  pub input_kind: InputKind,
+  /// What the submission was written in; only TeX is worth converting
+  pub class: SubmissionClass,
+  pub entry_count: usize,
+  pub uncompressed_bytes: u64,
+  pub output_path: String,
+  /// Fingerprint of the zip's contents, see `fingerprint::content_hash`
+  pub content_hash: String,
+  /// The sources matched the existing zip, which was left alone
+  pub unchanged: bool,
+  /// Where the replaced zip was archived to, under a retention policy
+  pub archived_previous: Option<String>,
+  /// Nested archives that were expanded, next to which their contents were added
+  pub expanded_archives: Vec<String>,
+  /// The submitter's `00README` directives, if any
+  pub directives: Option<SubmissionDirectives>,
+  /// Entries left out as the directives asked
+  pub ignored_entries: Vec<String>,
+  /// Also written to the paper directory, as `manifest.json`
+  pub manifest: Option<Manifest>,
+  /// The text encoding of a single-file TeX submission
+  pub encoding: Option<TextEncoding>,
+  /// Entries that were renamed or dropped for safety
+  pub sanitized_entries: Vec<SanitizedEntry>,
+  /// Problems that did not prevent writing the archive, e.g. entries that had to be skipped
+  pub warnings: Vec<String>,
+}
+
+#[derive(Debug, Error)]
+pub enum RepackageError {
+  #[error("I/O error at {path}: {source}")]
+  Io {
+    path: String,
+    #[source]
+    source: io::Error,
+  },
+  #[error("archive error for {path}: {message}")]
+  Archive { path: String, message: String },
+  #[error("unrecognizeable archive for {path}")]
+  Unrecognized { path: String },
+  #[error("no content in archive for {path}")]
+  Empty { path: String },
+  #[error("version retention failed: {0}")]
+  Retention(Box<Ar5ivError>),
+  /// A likely decompression bomb, stopped while reading
+  #[error("payload for {path} expands to {limit}")]
+  LimitExceeded {
+    path: String,
+    #[source]
+    limit: LimitExceeded,
+    /// Where the payload was set aside, if a quarantine directory was given
+    quarantined: Option<String>,
+  },
+}
+
+/// Knobs for `repackage_arxiv_download_with_options`; the default matches
+/// `repackage_arxiv_download`.
+#[derive(Debug, Clone, Default)]
+pub struct RepackageOptions {
+  /// The arXiv version of the payload, if known
+  pub version: Option<usize>,
+  /// Archive the zip being replaced, rather than overwrite it; for the zip format only
+  pub retention: Option<RetentionPolicy>,
+  pub format: OutputFormat,
+  pub limits: RepackageLimits,
+  /// Keep over-limit payloads here for inspection, e.g. `QUARANTINE_PATH`
+  pub quarantine_dir: Option<String>,
+  /// The file name the payload was served under, from its `Content-Disposition`
+  pub filename_hint: Option<String>,
+  /// How many levels of archives nested in the submission to unpack; 0 leaves them packed
+  pub expand_nested_depth: usize,
+  /// Write entries sorted by path, so the same sources always give the same zip.
+  /// Entries never carry upstream mtimes or permissions, and compression is the writer's
+  /// fixed deflate default, so the order is all that remains to pin down.
+  pub deterministic: bool,
+  /// Leave out the files a `00README` marks as `ignore`
+  pub drop_ignored: bool,
+  /// Re-encode single-file TeX submissions in legacy encodings to UTF-8
+  pub normalize_encoding: bool,
+  /// Files to leave out of multi-file submissions; stripped files are listed in the manifest
+  pub strip: StripPolicy,
+}
+
+impl RepackageError {
+  fn archive(path: &str, message: impl ToString) -> Self {
+    RepackageError::Archive {
+      path: path.to_owned(),
+      message: message.to_string(),
+    }
+  }
+}
+
+fn io_error_at(path: &str) -> impl FnOnce(io::Error) -> RepackageError + '_ {
+  move |source| RepackageError::Io {
+    path: path.to_owned(),
+    source,
+  }
+}
+
+impl From<RepackageError> for Ar5ivError {
+  fn from(e: RepackageError) -> Self {
+    match e {
+      RepackageError::Io { path, source } => Ar5ivError::CorpusIo { path, source },
+      RepackageError::Archive { path, message } => Ar5ivError::Archive { path, message },
+      RepackageError::Unrecognized { ref path } | RepackageError::Empty { ref path } => {
+        Ar5ivError::archive(path, &e)
+      },
+      RepackageError::Retention(e) => *e,
+      RepackageError::LimitExceeded { ref path, .. } => Ar5ivError::archive(path, &e),
+    }
+  }
+}
+
+pub fn repackage_arxiv_download(
+  memory: &mut [u8],
+  to_dir: String,
+  base_name: String,
+) -> Result<RepackageReport, RepackageError> {
+  repackage_arxiv_download_with_options(memory, to_dir, base_name, &RepackageOptions::default())
+}
+
+pub fn repackage_arxiv_download_with_options(
+  memory: &mut [u8],
+  to_dir: String,
+  base_name: String,
+  options: &RepackageOptions,
+) -> Result<RepackageReport, RepackageError> {
+  repackage_eprint(EPrintSource::Memory(memory), to_dir, base_name, options)
+}
+
+/// Where an e-print payload is read from, for `repackage_eprint`.
+pub enum EPrintSource<'a> {
+  Memory(&'a mut [u8]),
+  /// e.g. a spooled download, see `remote::fetch_eprint`
+  File(&'a Path),
+  /// Spooled to a hidden file in the output directory first, as libarchive reads
+  /// from memory or files only
+  Reader(Box<dyn Read + 'a>),
+}
+
+/// A payload the archive backend can open.
+enum Payload<'a> {
+  Memory(&'a mut [u8]),
+  File(&'a Path),
+}
+
+impl Payload<'_> {
+  fn open(&mut self, mode: ReadMode) -> Option<Box<dyn ArchiveReader + '_>> {
+    match self {
+      Payload::Memory(memory) => backend().open_memory(memory, mode).ok(),
+      Payload::File(path) => backend().open_file(path, mode).ok(),
+    }
+  }
+
+  fn len(&self) -> u64 {
+    match self {
+      Payload::Memory(memory) => memory.len() as u64,
+      Payload::File(path) => fs::metadata(path).map(|meta| meta.len()).unwrap_or(0),
+    }
+  }
+
+  fn starts_with(&self, magic: &[u8]) -> bool {
+    match self {
+      Payload::Memory(memory) => memory.starts_with(magic),
+      Payload::File(path) => {
+        let mut head = vec![0; magic.len()];
+        File::open(path)
+          .and_then(|mut file| file.read_exact(&mut head))
+          .is_ok_and(|_| head == magic)
+      },
+    }
+  }
+
+  fn save_to(&self, path: &Path) -> io::Result<()> {
+    match self {
+      Payload::Memory(memory) => fs::write(path, memory),
+      Payload::File(source) => fs::copy(source, path).map(|_| ()),
+    }
+  }
+}
+
+/// Repackage an e-print read from memory, a file, or any `Read` source.
+pub fn repackage_eprint(
+  source: EPrintSource,
+  to_dir: String,
+  base_name: String,
+  options: &RepackageOptions,
+) -> Result<RepackageReport, RepackageError> {
+  let _span = info_span!("repackage", paper = %base_name, dir = %to_dir).entered();
+  let result = match source {
+    EPrintSource::Memory(memory) => {
+      repackage_into(Payload::Memory(memory), &to_dir, &base_name, options)
+    },
+    EPrintSource::File(path) => repackage_into(Payload::File(path), &to_dir, &base_name, options),
+    EPrintSource::Reader(reader) => {
+      let spool_path = PathBuf::from(format!("{to_dir}/.{base_name}.payload.tmp"));
+      let max_bytes = options.limits.max_payload_bytes;
+      // one byte past the limit tells an over-limit payload from one right at it
+      let spooled = fs::create_dir_all(&to_dir)
+        .and_then(|_| File::create(&spool_path))
+        .and_then(|mut spool_file| {
+          io::copy(&mut reader.take(max_bytes.saturating_add(1)), &mut spool_file)
+        })
+        .map_err(io_error_at(&to_dir))
+        .and_then(|bytes| {
+          if bytes > max_bytes {
+            Err(RepackageError::LimitExceeded {
+              path: to_dir.clone(),
+              limit: LimitExceeded::PayloadBytes(max_bytes),
+              quarantined: None,
+            })
+          } else {
+            Ok(())
+          }
+        });
+      let result = spooled.and_then(|_| {
+        repackage_into(Payload::File(&spool_path), &to_dir, &base_name, options)
+      });
+      let _ = fs::remove_file(&spool_path);
+      result
+    },
+  };
+  match result.as_ref() {
+    Ok(report) => {
+      for warning in report.warnings.iter() {
+        warn!(warning, "repackaged with a warning");
+      }
+      for dropped in report.sanitized_entries.iter().filter(|e| e.sanitized.is_none()) {
+        warn!(entry = %dropped.original, reason = %dropped.reason, "dropped unsafe entry");
+      }
+    },
+    Err(e) => {
+      error!(error = %e, "repackaging failed");
+      metrics::record_repackage_failure();
+    },
+  }
+  result
+}
+
+fn repackage_into(
+  mut payload: Payload,
+  to_dir: &str,
+  base_name: &str,
+  options: &RepackageOptions,
+) -> Result<RepackageReport, RepackageError> {
+  fs::create_dir_all(to_dir).map_err(io_error_at(to_dir))?;
+  let to_path = options.format.output_path(to_dir, base_name);
+  // Never write the corpus zip in place: a crash, a full disk or a broken payload would leave a
+  // truncated archive where the previous good one was. Write a hidden sibling, check it, rename.
+  let tmp_path = format!("{to_dir}/.{}.tmp", options.format.file_name(base_name));
+  let written = write_output(&mut payload, to_dir, base_name, &tmp_path, options);
+  let mut result = written.and_then(|(mut report, written)| {
+    // the libarchive writer is closed when dropped, at the end of `write_output`
+    File::open(&tmp_path)
+      .and_then(|tmp_file| tmp_file.sync_all())
+      .map_err(io_error_at(&tmp_path))?;
+    report.content_hash = validate_output(&tmp_path, written)?;
+    if existing_hash(&to_path, to_dir, base_name).as_ref() == Some(&report.content_hash) {
+      // e.g. a metadata-only update: keep the zip, and its mtime, as they are
+      remove_output(&tmp_path);
+      record_manifest(to_dir, report.manifest.as_ref()).map_err(io_error_at(to_dir))?;
+      report.unchanged = true;
+      report.output_path = to_path;
+      return Ok(report);
+    }
+    let retention = match options.format {
+      OutputFormat::Zip => options.retention,
+      _ => None,
+    };
+    let retained = match retention {
+      Some(policy) => retain_current(to_dir, base_name, policy, options.version)
+        .map_err(|e| RepackageError::Retention(Box::new(e)))?,
+      None => None,
+    };
+    if let Err(e) = install(&tmp_path, &to_path) {
+      // the previous zip is still in place, and need not be archived
+      if let Some(retained) = retained.as_ref() {
+        if let Err(e) = discard_retained(retained) {
+          warn!(error = %e, "could not discard the retained version");
+        }
+      }
+      return Err(e);
+    }
+    if let (Some(policy), Some(retained)) = (retention, retained) {
+      prune_versions(to_dir, base_name, policy)
+        .map_err(|e| RepackageError::Retention(Box::new(e)))?;
+      report.archived_previous = Some(retained.path.display().to_string());
+    }
+    record_version(to_dir, base_name, options.version).map_err(io_error_at(to_dir))?;
+    record_hash(to_dir, base_name, &report.content_hash).map_err(io_error_at(to_dir))?;
+    // only now, as it describes the output just installed
+    record_manifest(to_dir, report.manifest.as_ref()).map_err(io_error_at(to_dir))?;
+    // persist the rename itself
+    File::open(to_dir)
+      .and_then(|dir| dir.sync_all())
+      .map_err(io_error_at(to_dir))?;
+    report.output_path = to_path;
+    Ok(report)
   });
-  // We'll write out a ZIP file for each entry
-  let mut archive_writer_new = Writer::new()
-    .unwrap()
-    //.add_filter(ArchiveFilter::Lzip)
-    // .set_compression(ArchiveFilter::None)
-    .set_format(ArchiveFormat::Zip);
-  let to_path = format!("{to_dir}/{base_name}.zip");
-  archive_writer_new
-    .open_filename(&to_path)
-    .unwrap();
+  if result.is_err() {
+    remove_output(&tmp_path);
+  }
+  if let (Err(RepackageError::LimitExceeded { limit, quarantined, .. }), Some(quarantine_dir)) =
+    (&mut result, &options.quarantine_dir)
+  {
+    match quarantine(&payload, quarantine_dir, base_name, limit) {
+      Ok(payload_path) => *quarantined = Some(payload_path),
+      Err(e) => warn!(error = %e, "could not quarantine payload"),
+    }
+  }
+  result
+}
+
+/// The fingerprint of the output at `to_path`, from its sidecar, or computed for outputs written
+/// before fingerprints were recorded. `None` if there is no (readable) output.
+fn existing_hash(to_path: &str, to_dir: &str, base_name: &str) -> Option<String> {
+  if !Path::new(to_path).exists() {
+    return None;
+  }
+  stored_hash(to_dir, base_name).or_else(|| content_hash(to_path).ok().map(|(_, hash)| hash))
+}
+
+/// Reopen a freshly written output and read it through, expecting `expected_entries` entries.
+/// Returns its content fingerprint.
+fn validate_output(path: &str, expected_entries: usize) -> Result<String, RepackageError> {
+  let (entries, hash) = content_hash(path)?;
+  if entries != expected_entries {
+    return Err(RepackageError::archive(
+      path,
+      format!("written output has {entries} entries, expected {expected_entries}"),
+    ));
+  }
+  Ok(hash)
+}
+
+/// Repackage `payload` in the configured format at `output_path`, returning the report
+/// and the number of entries actually written.
+fn write_output(
+  payload: &mut Payload,
+  to_dir: &str,
+  base_name: &str,
+  output_path: &str,
+  options: &RepackageOptions,
+) -> Result<(RepackageReport, usize), RepackageError> {
+  let over_limit = |limit| RepackageError::LimitExceeded {
+    path: to_dir.to_owned(),
+    limit,
+    quarantined: None,
+  };
+  let read_failed = |failure| match failure {
+    ReadFailure::Limit(limit) => over_limit(limit),
+    ReadFailure::Unreadable(e) => {
+      RepackageError::archive(to_dir, format!("does not read through: {e}"))
+    },
+  };
+  // We'll write out a ZIP file (or the configured format) for each entry
+  let mut sink = options.format.create(output_path)?;
+  let mut report = RepackageReport {
+    input_kind: InputKind::Tar,
+    class: SubmissionClass::Unknown,
+    entry_count: 0,
+    uncompressed_bytes: 0,
+    output_path: output_path.to_owned(),
+    content_hash: String::new(),
+    unchanged: false,
+    archived_previous: None,
+    expanded_archives: Vec::new(),
+    directives: None,
+    ignored_entries: Vec::new(),
+    manifest: None,
+    encoding: None,
+    sanitized_entries: Vec::new(),
+    warnings: Vec::new(),
+  };
+  let mut written = 0;
 
   // Careful here, some of arXiv's .gz files are really plain-text TeX files (surprise!!!)
   let mut raw_read_needed = false;
-  match Reader::new()
-    .unwrap()
-    .support_filter_all()
-    .support_format_all()
-    .open_memory(memory)
-  {
-    Err(_) => raw_read_needed = true,
-    Ok(archive_reader) => {
-      let mut file_count = 0;
-      while let Ok(e) = archive_reader.next_header() {
-        file_count += 1;
-        match archive_writer_new.write_header(e) {
-          Ok(_) => {}, // TODO: If we need to print an error message, we can do so later.
-          Err(e2) => println!("Header write failed: {e2:?}"),
-        };
-        while let Ok(chunk) = archive_reader.read_data(BUFFER_SIZE) {
-          archive_writer_new.write_data(chunk).unwrap();
+  let compressed_bytes = payload.len();
+  let is_gzip = payload.starts_with(&GZIP_MAGIC);
+  match payload.open(ReadMode::Archive) {
+    None => raw_read_needed = true,
+    Some(mut archive_reader) => {
+      // Entries are rewritten under their sanitized paths, rather than copied header and all.
+      let mut tracker = LimitTracker::new(options.limits, compressed_bytes);
+      let entries = read_sanitized_entries(archive_reader.as_mut(), &mut report, &mut tracker)
+        .map_err(read_failed)?;
+      let mut entries = expand_nested_archives(
+        entries,
+        options.expand_nested_depth,
+        &mut report,
+        &mut tracker,
+      )
+      .map_err(over_limit)?;
+      report.class = classify_members(
+        entries
+          .iter()
+          .map(|entry| (entry.path.as_str(), entry.data.as_slice())),
+      );
+      apply_directives(&mut entries, &mut report, options.drop_ignored);
+      let stripped = options.strip.apply(&mut entries);
+      let mut manifest = build_manifest(
+        entries
+          .iter()
+          .map(|entry| (entry.path.as_str(), entry.data.as_slice())),
+        base_name,
+        report.directives.as_ref(),
+      );
+      manifest.stripped = stripped;
+      report.manifest = Some(manifest);
+      if options.deterministic {
+        entries.sort_by(|a, b| a.path.cmp(&b.path));
+      }
+      for entry in entries {
+        // a skipped entry is only a warning; a half-written one fails validation later on
+        match sink.write_entry(&entry.path, entry.data) {
+          Ok(_) => written += 1,
+          Err(e2) => {
+            report
+              .warnings
+              .push(format!("write failed for {:?}: {e2}", entry.path));
+          },
         }
       }
-      if file_count == 0 {
+      if report.entry_count == 0 {
         // Special case (bug? in libarchive crate), single file in .gz
         raw_read_needed = true;
       }
@@ -134,46 +616,317 @@ pub fn repackage_arxiv_download(memory: &mut [u8], to_dir: String, base_name: St
   }
 
   if raw_read_needed {
-    let raw_reader_new = Reader::new()
-      .unwrap()
-      .support_filter_all()
-      .support_format_raw()
-      .open_memory(memory);
-    match raw_reader_new {
-      Ok(raw_reader) => match raw_reader.next_header() {
-        Ok(_) => {
-          single_file_transfer(&default_tex_target, &raw_reader, &mut archive_writer_new);
-        },
-        Err(_) => println!("No content in archive: {to_dir:?}"),
+    let mut raw_reader = payload
+      .open(ReadMode::Raw)
+      .ok_or_else(|| RepackageError::Unrecognized {
+        path: to_dir.to_owned(),
+      })?;
+    match raw_reader.next_entry() {
+      Ok(Some(_)) => {},
+      Ok(None) => {
+        return Err(RepackageError::Empty {
+          path: to_dir.to_owned(),
+        })
       },
-      Err(_) => println!("Unrecognizeable archive: {to_dir:?}"),
+      Err(e) => return Err(RepackageError::archive(to_dir, format!("does not read: {e}"))),
+    }
+    report.input_kind = if is_gzip {
+      InputKind::GzipSingleFile
+    } else {
+      InputKind::Raw
+    };
+    report.entry_count = 1;
+    let mut tracker = LimitTracker::new(options.limits, compressed_bytes);
+    tracker.start_entry().map_err(over_limit)?;
+    let transferred = single_file_transfer(
+      base_name,
+      options,
+      raw_reader.as_mut(),
+      sink.as_mut(),
+      &mut tracker,
+    )
+    .map_err(|e| match e {
+      RepackageError::LimitExceeded { limit, .. } => over_limit(limit),
+      e => e,
+    })?;
+    report.class = transferred.class;
+    report.uncompressed_bytes = transferred.bytes;
+    written = 1;
+    if report.class.is_tex() {
+      let target = format!("{base_name}.{}", report.class.extension());
+      let mut manifest = single_file_manifest(&target);
+      if let Some(original) = transferred.encoding.as_ref().and_then(|e| e.original.clone()) {
+        manifest.files.entry(FileRole::of(&original)).or_default().push(original);
+        manifest.encoding = transferred.encoding.clone();
+        written += 1;
+      }
+      report.manifest = Some(manifest);
     }
+    report.encoding = transferred.encoding;
   }
+  Ok((report, written))
 }
 
+/// Find the submission's `00README` directives, record them in the report and add their
+/// normalized form to the `entries`, then drop the ignored entries if so asked.
+fn apply_directives(
+  entries: &mut Vec<SourceEntry>,
+  report: &mut RepackageReport,
+  drop_ignored: bool,
+) {
+  let found = find_directives(
+    entries
+      .iter()
+      .map(|entry| (entry.path.as_str(), entry.data.as_slice())),
+  );
+  let directives = match found {
+    None => return,
+    Some(Ok(directives)) => directives,
+    Some(Err(e)) => {
+      report.warnings.push(format!("unreadable 00README.json: {e}"));
+      return;
+    },
+  };
+  if drop_ignored {
+    entries.retain(|entry| {
+      let ignored = directives.is_ignored(&entry.path);
+      if ignored {
+        report.ignored_entries.push(entry.path.clone());
+      }
+      !ignored
+    });
+  }
+  if entries.iter().any(|entry| entry.path == DIRECTIVES_ENTRY) {
+    report
+      .warnings
+      .push(format!("submission has its own {DIRECTIVES_ENTRY}, not overwritten"));
+  } else {
+    match serde_json::to_vec_pretty(&directives) {
+      Ok(data) => entries.push(SourceEntry {
+        path: DIRECTIVES_ENTRY.to_owned(),
+        data,
+      }),
+      Err(e) => report.warnings.push(format!("could not write directives: {e}")),
+    }
+  }
+  report.directives = Some(directives);
+}
 
-/// Transfer the data contained within `Reader` to a `Writer`, assuming it was a single file
-pub fn single_file_transfer(tex_target: &str, reader: &Reader, writer: &mut Writer) {
+/// A regular file read out of an e-print, under its sanitized path.
+struct SourceEntry {
+  path: String,
+  data: Vec<u8>,
+}
+
+/// Why the entries of an archive could not all be read.
+enum ReadFailure {
+  Limit(LimitExceeded),
+  /// The archive breaks off or is corrupt past its first entry
+  Unreadable(io::Error),
+}
+
+impl From<LimitExceeded> for ReadFailure {
+  fn from(limit: LimitExceeded) -> Self { ReadFailure::Limit(limit) }
+}
+
+/// A link entry, resolved once all entries are read, as its target may come later.
+struct PendingLink {
+  original: String,
+  path: String,
+  /// `hardlink` or `symlink`
+  kind: &'static str,
+  target: String,
+  /// The target as a normalized path inside the archive, if it is one
+  resolved: Option<String>,
+}
+
+/// Read all entries of an archive, keeping regular files under normalized paths.
+/// Entries escaping the paper directory are dropped, links are resolved to copies of
+/// their (in-archive) targets or dropped; all such decisions are listed in the report.
+/// Stops as soon as the `limits` are exceeded, without reading any further, which also bounds
+/// the memory the entries take up. An archive whose very first header does not read is taken
+/// for no archive at all and gives no entries; one that breaks off later fails.
+fn read_sanitized_entries(
+  reader: &mut dyn ArchiveReader,
+  report: &mut RepackageReport,
+  limits: &mut LimitTracker,
+) -> Result<Vec<SourceEntry>, ReadFailure> {
+  let mut entries: Vec<SourceEntry> = Vec::new();
+  let mut index: HashMap<String, usize> = HashMap::new();
+  let mut links: Vec<PendingLink> = Vec::new();
+  let mut headers_read = 0;
+  loop {
+    let e = match reader.next_entry() {
+      Ok(Some(e)) => e,
+      Ok(None) => break,
+      Err(_) if headers_read == 0 => break,
+      Err(e) => return Err(ReadFailure::Unreadable(e)),
+    };
+    headers_read += 1;
+    limits.start_entry()?;
+    report.entry_count += 1;
+    let original = e.pathname;
+    let mut data = Vec::new();
+    while let Some(chunk) = reader.read_chunk().map_err(ReadFailure::Unreadable)? {
+      limits.add_bytes(chunk.len())?;
+      data.extend(chunk);
+    }
+    report.uncompressed_bytes += data.len() as u64;
+    if original.ends_with('/') {
+      continue; // directories are implied by the paths of their files
+    }
+    let Some(path) = normalize_entry_path(&original) else {
+      report.sanitized_entries.push(SanitizedEntry {
+        original,
+        sanitized: None,
+        reason: String::from("escapes the paper directory, or an empty or NUL path"),
+      });
+      continue;
+    };
+    match (e.hardlink, e.symlink) {
+      (Some(target), _) => links.push(PendingLink {
+        resolved: normalize_entry_path(&target),
+        kind: "hardlink",
+        original,
+        path,
+        target,
+      }),
+      (None, Some(target)) => links.push(PendingLink {
+        resolved: resolve_link_target(&path, &target),
+        kind: "symlink",
+        original,
+        path,
+        target,
+      }),
+      (None, None) => {
+        if index.contains_key(&path) {
+          report.sanitized_entries.push(SanitizedEntry {
+            reason: format!("duplicate of {path:?}"),
+            original,
+            sanitized: None,
+          });
+          continue;
+        }
+        if path != original {
+          report.sanitized_entries.push(SanitizedEntry {
+            original,
+            sanitized: Some(path.clone()),
+            reason: String::from("normalized path"),
+          });
+        }
+        index.insert(path.clone(), entries.len());
+        entries.push(SourceEntry { path, data });
+      },
+    }
+  }
+  // Links may point anywhere in the archive, also to other links: resolve them in rounds,
+  // until a round resolves none. Copies count against the limits too, or links could multiply
+  // a payload.
+  loop {
+    let pending = links.len();
+    let mut unresolved = Vec::new();
+    for link in links {
+      let Some(&target_index) = link.resolved.as_ref().and_then(|target| index.get(target)) else {
+        unresolved.push(link);
+        continue;
+      };
+      if index.contains_key(&link.path) {
+        report.sanitized_entries.push(SanitizedEntry {
+          reason: format!("duplicate of {:?}", link.path),
+          original: link.original,
+          sanitized: None,
+        });
+        continue;
+      }
+      limits.add_copy(entries[target_index].data.len())?;
+      report.sanitized_entries.push(SanitizedEntry {
+        original: link.original,
+        sanitized: Some(link.path.clone()),
+        reason: format!("{} to {:?} resolved to a copy", link.kind, link.target),
+      });
+      let data = entries[target_index].data.clone();
+      index.insert(link.path.clone(), entries.len());
+      entries.push(SourceEntry { path: link.path, data });
+    }
+    links = unresolved;
+    if links.len() == pending {
+      break;
+    }
+  }
+  for link in links {
+    report.sanitized_entries.push(SanitizedEntry {
+      original: link.original,
+      sanitized: None,
+      reason: format!("{} to {:?}, not a file in the archive", link.kind, link.target),
+    });
+  }
+  Ok(entries)
+}
+
+
+/// What `single_file_transfer` found out about the file.
+#[derive(Debug, Clone)]
+pub struct SingleFileTransfer {
+  pub class: SubmissionClass,
+  /// Bytes read, before any re-encoding
+  pub bytes: u64,
+  /// Detected for TeX files only
+  pub encoding: Option<TextEncoding>,
+}
+
+/// Transfer the data contained within `reader` to an output, assuming it was a single file.
+/// The file is named `{base_name}.{ext}` after its detected class, e.g. `.pdf` for PDF-only
+/// submissions, and TeX in a legacy encoding is re-encoded if `options` ask for it, keeping the
+/// original next to it.
+/// Fails with `LimitExceeded` once `limits` are hit.
+pub fn single_file_transfer(
+  base_name: &str,
+  options: &RepackageOptions,
+  reader: &mut dyn ArchiveReader,
+  sink: &mut dyn OutputSink,
+  limits: &mut LimitTracker,
+) -> Result<SingleFileTransfer, RepackageError> {
   // In a "raw" read, we don't know the data size in advance. So we bite the
   // bullet and read the usually tiny tex file in memory,
   // obtaining a size estimate
   let mut raw_data = Vec::new();
-  while let Ok(chunk) = reader.read_data(BUFFER_SIZE) {
+  while let Some(chunk) = reader
+    .read_chunk()
+    .map_err(|e| RepackageError::archive(base_name, format!("does not read through: {e}")))?
+  {
+    limits
+      .add_bytes(chunk.len())
+      .map_err(|limit| RepackageError::LimitExceeded {
+        path: base_name.to_owned(),
+        limit,
+        quarantined: None,
+      })?;
     raw_data.extend(chunk.into_iter());
   }
-  let mut ok_header = false;
-  match writer.write_header_new(tex_target, raw_data.len() as i64) {
-    Ok(_) => {
-      ok_header = true;
-    },
-    Err(e) => {
-      println!("Couldn't write header: {e:?}");
-    },
-  }
-  if ok_header {
-    match writer.write_data(raw_data) {
-      Ok(_) => {},
-      Err(e) => println!("Failed to write data to {tex_target:?} because {e:?}"),
-    };
+  let bytes = raw_data.len() as u64;
+  let class = classify_single_file(&raw_data, options.filename_hint.as_deref());
+  let target = format!("{base_name}.{}", class.extension());
+  let mut encoding = None;
+  if class.is_tex() {
+    let detected = detect_encoding(&raw_data);
+    let normalized = options.normalize_encoding && detected != encoding_rs::UTF_8;
+    let mut original = None;
+    if normalized {
+      let original_path = format!("{target}{ORIGINAL_SUFFIX}");
+      let utf8_data = normalize_to_utf8(&raw_data, detected);
+      sink.write_entry(&original_path, std::mem::replace(&mut raw_data, utf8_data))?;
+      original = Some(original_path);
+    }
+    encoding = Some(TextEncoding {
+      detected: detected.name().to_owned(),
+      normalized,
+      original,
+    });
   }
+  sink.write_entry(&target, raw_data)?;
+  Ok(SingleFileTransfer {
+    class,
+    bytes,
+    encoding,
+  })
 }
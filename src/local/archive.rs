@@ -0,0 +1,77 @@
+//! The archive backend: reading e-prints and writing outputs, entry by entry.
+//!
+//! Two implementations are available, chosen with cargo features: `libarchive` (the default),
+//! through the `libarchive-sys` bindings, and `pure-rust`, through the flate2, tar, zip, zstd
+//! and bzip2 crates, which need no system libarchive. With both enabled, libarchive is used.
+//! The pure-Rust backend reads tar (possibly gzip, bzip2 or zstd compressed) and zip, which is
+//! what arXiv serves; libarchive reads about anything. `tests/archive_backends.rs` holds both
+//! to the same behavior on those.
+use std::io;
+use std::path::Path;
+
+#[cfg(feature = "libarchive")]
+pub mod libarchive;
+#[cfg(feature = "pure-rust")]
+pub mod pure;
+
+#[cfg(not(any(feature = "libarchive", feature = "pure-rust")))]
+compile_error!("no archive backend, enable the `libarchive` or the `pure-rust` feature");
+
+/// What an archive says about one of its entries.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct EntryHeader {
+  /// As stored, directories usually with a trailing `/`
+  pub pathname: String,
+  /// Seconds since the epoch
+  pub mtime: i64,
+  pub symlink: Option<String>,
+  pub hardlink: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReadMode {
+  /// A tar or zip, possibly compressed
+  Archive,
+  /// A single, possibly compressed, file, read as one entry named `data`
+  Raw,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WriteFormat {
+  Zip,
+  TarZstd,
+  TarGzip,
+}
+
+/// Reads an archive through, one entry at a time.
+pub trait ArchiveReader {
+  /// The header of the next entry, `None` past the last one. Skips what is left of the
+  /// current entry's data.
+  fn next_entry(&mut self) -> io::Result<Option<EntryHeader>>;
+  /// The next chunk of the current entry's data, `None` past its end.
+  fn read_chunk(&mut self) -> io::Result<Option<Vec<u8>>>;
+}
+
+/// Writes regular files into a new archive, which is closed when dropped.
+pub trait ArchiveWriter {
+  fn write_entry(&mut self, path: &str, data: Vec<u8>) -> io::Result<()>;
+}
+
+pub trait ArchiveBackend: Send + Sync {
+  fn name(&self) -> &'static str;
+  fn open_file(&self, path: &Path, mode: ReadMode) -> io::Result<Box<dyn ArchiveReader>>;
+  fn open_memory<'a>(
+    &self,
+    memory: &'a mut [u8],
+    mode: ReadMode,
+  ) -> io::Result<Box<dyn ArchiveReader + 'a>>;
+  fn create(&self, path: &Path, format: WriteFormat) -> io::Result<Box<dyn ArchiveWriter>>;
+}
+
+/// The backend this build was configured with.
+#[cfg(feature = "libarchive")]
+pub fn backend() -> &'static dyn ArchiveBackend { &libarchive::LibArchive }
+
+/// The backend this build was configured with.
+#[cfg(all(feature = "pure-rust", not(feature = "libarchive")))]
+pub fn backend() -> &'static dyn ArchiveBackend { &pure::PureRust }
@@ -0,0 +1,121 @@
+//! The libarchive backend, via the `libarchive-sys` bindings.
+use std::io;
+use std::marker::PhantomData;
+use std::path::Path;
+
+use Archive::*;
+
+use super::{ArchiveBackend, ArchiveReader, ArchiveWriter, EntryHeader, ReadMode, WriteFormat};
+use crate::local::BUFFER_SIZE;
+
+pub struct LibArchive;
+
+/// `ARCHIVE_EOF`, the status of `archive_read_next_header` past the last entry
+const ARCHIVE_EOF: i32 = 1;
+
+fn archive_error(e: ArchiveError) -> io::Error { io::Error::other(format!("{e:?}")) }
+
+/// The bindings report the end of the archive, and of an entry's data, as errors. Only those
+/// read as `None`: a truncated archive or a failed CRC must not pass for a clean end.
+fn or_end<T>(result: std::result::Result<T, ArchiveError>) -> io::Result<Option<T>> {
+  match result {
+    Ok(value) => Ok(Some(value)),
+    Err(ArchiveError::Consumed) | Err(ArchiveError::Sys(ErrCode(ARCHIVE_EOF), _)) => Ok(None),
+    Err(e) => Err(io::Error::new(io::ErrorKind::InvalidData, format!("{e:?}"))),
+  }
+}
+
+fn new_reader(mode: ReadMode) -> io::Result<Reader> {
+  let reader = Reader::new().map_err(archive_error)?.support_filter_all();
+  Ok(match mode {
+    ReadMode::Archive => reader.support_format_all(),
+    ReadMode::Raw => reader.support_format_raw(),
+  })
+}
+
+impl ArchiveBackend for LibArchive {
+  fn name(&self) -> &'static str { "libarchive" }
+
+  fn open_file(&self, path: &Path, mode: ReadMode) -> io::Result<Box<dyn ArchiveReader>> {
+    let path = path
+      .to_str()
+      .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "non-UTF-8 path"))?;
+    let reader = new_reader(mode)?
+      .open_filename(path, BUFFER_SIZE)
+      .map_err(archive_error)?;
+    Ok(Box::new(LibArchiveReader {
+      reader,
+      memory: PhantomData,
+    }))
+  }
+
+  fn open_memory<'a>(
+    &self,
+    memory: &'a mut [u8],
+    mode: ReadMode,
+  ) -> io::Result<Box<dyn ArchiveReader + 'a>> {
+    let reader = new_reader(mode)?
+      .open_memory(memory)
+      .map_err(archive_error)?;
+    Ok(Box::new(LibArchiveReader {
+      reader,
+      memory: PhantomData,
+    }))
+  }
+
+  fn create(&self, path: &Path, format: WriteFormat) -> io::Result<Box<dyn ArchiveWriter>> {
+    let path = path
+      .to_str()
+      .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "non-UTF-8 path"))?;
+    let writer = Writer::new().map_err(archive_error)?;
+    let mut writer = match format {
+      WriteFormat::Zip => writer.set_format(ArchiveFormat::Zip),
+      WriteFormat::TarZstd => writer
+        .set_format(ArchiveFormat::Tar)
+        .add_filter(ArchiveFilter::Zstd),
+      WriteFormat::TarGzip => writer
+        .set_format(ArchiveFormat::Tar)
+        .add_filter(ArchiveFilter::Gzip),
+    };
+    writer.open_filename(path).map_err(archive_error)?;
+    Ok(Box::new(LibArchiveWriter { writer }))
+  }
+}
+
+/// The reader keeps pointing into the memory it was opened on.
+struct LibArchiveReader<'a> {
+  reader: Reader,
+  memory: PhantomData<&'a mut [u8]>,
+}
+
+impl ArchiveReader for LibArchiveReader<'_> {
+  fn next_entry(&mut self) -> io::Result<Option<EntryHeader>> {
+    Ok(or_end(self.reader.next_header())?.map(|entry| EntryHeader {
+      pathname: entry.pathname(),
+      mtime: entry.mtime(),
+      symlink: entry.symlink(),
+      hardlink: entry.hardlink(),
+    }))
+  }
+
+  fn read_chunk(&mut self) -> io::Result<Option<Vec<u8>>> {
+    or_end(self.reader.read_data(BUFFER_SIZE))
+  }
+}
+
+struct LibArchiveWriter {
+  writer: Writer,
+}
+
+impl ArchiveWriter for LibArchiveWriter {
+  fn write_entry(&mut self, path: &str, data: Vec<u8>) -> io::Result<()> {
+    self
+      .writer
+      .write_header_new(path, data.len() as i64)
+      .map_err(|e| io::Error::other(format!("couldn't write header: {e:?}")))?;
+    self
+      .writer
+      .write_data(data)
+      .map_err(|e| io::Error::other(format!("failed to write data: {e:?}")))
+  }
+}
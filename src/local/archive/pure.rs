@@ -0,0 +1,492 @@
+//! The pure-Rust backend: tar and zip, as is or gzip, bzip2 or zstd compressed.
+//!
+//! Entries are streamed chunk by chunk, as with libarchive, so that the repackaging limits
+//! can stop a zip bomb before it is inflated. Tar headers are parsed by the `tar` crate, one
+//! block at a time; zip entries are located via the central directory and inflated directly,
+//! with their CRC checked at the end.
+use std::fs::File;
+use std::io::{self, prelude::*, SeekFrom};
+use std::path::Path;
+use std::vec;
+
+use chrono::NaiveDate;
+use flate2::read::{DeflateDecoder, MultiGzDecoder};
+use flate2::write::GzEncoder;
+use flate2::{Compression, Crc};
+use tar::{EntryType, Header, PaxExtensions};
+use zip::write::FileOptions;
+use zip::{CompressionMethod, DateTime, ZipArchive, ZipWriter};
+
+use super::{ArchiveBackend, ArchiveReader, ArchiveWriter, EntryHeader, ReadMode, WriteFormat};
+use crate::local::{BUFFER_SIZE, GZIP_MAGIC};
+
+const BZIP2_MAGIC: [u8; 3] = *b"BZh";
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+const ZIP_MAGIC: [u8; 4] = *b"PK\x03\x04";
+const EMPTY_ZIP_MAGIC: [u8; 4] = *b"PK\x05\x06";
+const TAR_BLOCK: usize = 512;
+/// GNU long names and pax headers are read whole, up to this size
+const MAX_EXTENSION_BYTES: u64 = 1 << 20;
+
+pub struct PureRust;
+
+trait ReadSeek: Read + Seek {}
+impl<T: Read + Seek> ReadSeek for T {}
+
+impl ArchiveBackend for PureRust {
+  fn name(&self) -> &'static str { "pure-rust" }
+
+  fn open_file(&self, path: &Path, mode: ReadMode) -> io::Result<Box<dyn ArchiveReader>> {
+    open(File::open(path)?, mode)
+  }
+
+  fn open_memory<'a>(
+    &self,
+    memory: &'a mut [u8],
+    mode: ReadMode,
+  ) -> io::Result<Box<dyn ArchiveReader + 'a>> {
+    open(io::Cursor::new(&*memory), mode)
+  }
+
+  fn create(&self, path: &Path, format: WriteFormat) -> io::Result<Box<dyn ArchiveWriter>> {
+    let file = File::create(path)?;
+    Ok(match format {
+      WriteFormat::Zip => Box::new(PureZipWriter {
+        zip: ZipWriter::new(file),
+      }),
+      WriteFormat::TarGzip => Box::new(PureTarWriter {
+        builder: tar::Builder::new(GzEncoder::new(file, Compression::default())),
+      }),
+      WriteFormat::TarZstd => Box::new(PureTarWriter {
+        builder: tar::Builder::new(zstd::Encoder::new(file, 0)?.auto_finish()),
+      }),
+    })
+  }
+}
+
+/// Fill `buf` as far as the source allows, returning how much was read.
+fn read_up_to(source: &mut dyn Read, buf: &mut [u8]) -> io::Result<usize> {
+  let mut filled = 0;
+  while filled < buf.len() {
+    match source.read(&mut buf[filled..]) {
+      Ok(0) => break,
+      Ok(read) => filled += read,
+      Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+      Err(e) => return Err(e),
+    }
+  }
+  Ok(filled)
+}
+
+fn open<'a, R: Read + Seek + 'a>(
+  mut source: R,
+  mode: ReadMode,
+) -> io::Result<Box<dyn ArchiveReader + 'a>> {
+  let mut magic = [0; 4];
+  let magic_len = read_up_to(&mut source, &mut magic)?;
+  let magic = &magic[..magic_len];
+  source.seek(SeekFrom::Start(0))?;
+  if mode == ReadMode::Archive && (magic == ZIP_MAGIC || magic == EMPTY_ZIP_MAGIC) {
+    return Ok(Box::new(ZipReader::new(Box::new(source))?));
+  }
+  let mut decoded: Box<dyn Read + 'a> = if magic.starts_with(&GZIP_MAGIC) {
+    Box::new(MultiGzDecoder::new(source))
+  } else if magic.starts_with(&BZIP2_MAGIC) {
+    Box::new(bzip2::read::MultiBzDecoder::new(source))
+  } else if magic == ZSTD_MAGIC {
+    Box::new(zstd::Decoder::new(source)?)
+  } else {
+    Box::new(source)
+  };
+  match mode {
+    ReadMode::Raw => Ok(Box::new(RawReader {
+      source: decoded,
+      pending: None,
+      started: false,
+    })),
+    ReadMode::Archive => {
+      let mut head = vec![0; TAR_BLOCK];
+      let head_len = read_up_to(&mut decoded, &mut head)?;
+      head.truncate(head_len);
+      if !looks_like_tar(&head) {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "not a tar or zip archive"));
+      }
+      Ok(Box::new(TarReader {
+        source: Box::new(io::Cursor::new(head).chain(decoded)),
+        remaining: 0,
+        padding: 0,
+      }))
+    },
+  }
+}
+
+/// A single file, read as one entry named `data`, like libarchive's raw format.
+struct RawReader<'a> {
+  source: Box<dyn Read + 'a>,
+  /// The first chunk, read ahead to tell an empty payload
+  pending: Option<Vec<u8>>,
+  started: bool,
+}
+
+impl RawReader<'_> {
+  fn read_next(&mut self) -> io::Result<Option<Vec<u8>>> {
+    let mut chunk = vec![0; BUFFER_SIZE];
+    let read = read_up_to(&mut self.source, &mut chunk)?;
+    chunk.truncate(read);
+    Ok((read > 0).then_some(chunk))
+  }
+}
+
+impl ArchiveReader for RawReader<'_> {
+  fn next_entry(&mut self) -> io::Result<Option<EntryHeader>> {
+    if self.started {
+      return Ok(None);
+    }
+    self.started = true;
+    self.pending = self.read_next()?;
+    Ok(self.pending.is_some().then(|| EntryHeader {
+      pathname: String::from("data"),
+      ..EntryHeader::default()
+    }))
+  }
+
+  fn read_chunk(&mut self) -> io::Result<Option<Vec<u8>>> {
+    match self.pending.take() {
+      Some(chunk) => Ok(Some(chunk)),
+      None if self.started => self.read_next(),
+      None => Ok(None),
+    }
+  }
+}
+
+/// Whether `head`, the first block of a payload, is a tar header: one with a valid checksum,
+/// which old v7 tars without the `ustar` magic have too, or the zero block of an empty tar.
+fn looks_like_tar(head: &[u8]) -> bool {
+  if head.len() != TAR_BLOCK {
+    return false;
+  }
+  head.iter().all(|byte| *byte == 0) || has_valid_checksum(Header::from_byte_slice(head))
+}
+
+fn has_valid_checksum(header: &Header) -> bool {
+  let bytes = header.as_bytes();
+  // the checksum field itself counts as spaces
+  let sum: u32 = bytes[..148]
+    .iter()
+    .chain(&[b' '; 8])
+    .chain(&bytes[156..])
+    .map(|byte| *byte as u32)
+    .sum();
+  header.cksum().is_ok_and(|cksum| cksum == sum)
+}
+
+struct TarReader<'a> {
+  source: Box<dyn Read + 'a>,
+  /// Data left in the current entry
+  remaining: u64,
+  /// Padding after the current entry's data, up to the next block
+  padding: u64,
+}
+
+impl TarReader<'_> {
+  fn skip(&mut self, bytes: u64) -> io::Result<()> {
+    let skipped = io::copy(&mut (&mut self.source).take(bytes), &mut io::sink())?;
+    if skipped < bytes {
+      return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "truncated tar archive"));
+    }
+    Ok(())
+  }
+
+  /// The data of an extension entry (long name, pax header), skipping its padding.
+  fn read_extension(&mut self, size: u64) -> io::Result<Vec<u8>> {
+    if size > MAX_EXTENSION_BYTES {
+      return Err(io::Error::new(io::ErrorKind::InvalidData, "oversized tar extension header"));
+    }
+    let mut data = vec![0; size as usize];
+    self.source.read_exact(&mut data)?;
+    self.skip(block_padding(size))?;
+    Ok(data)
+  }
+}
+
+fn block_padding(size: u64) -> u64 {
+  let block = TAR_BLOCK as u64;
+  (block - size % block) % block
+}
+
+/// A name from an extension entry, which may be NUL-terminated.
+fn extension_name(data: &[u8]) -> String {
+  let end = data.iter().position(|byte| *byte == 0).unwrap_or(data.len());
+  String::from_utf8_lossy(&data[..end]).into_owned()
+}
+
+impl ArchiveReader for TarReader<'_> {
+  fn next_entry(&mut self) -> io::Result<Option<EntryHeader>> {
+    self.skip(self.remaining + self.padding)?;
+    self.remaining = 0;
+    self.padding = 0;
+    let mut long_name = None;
+    let mut long_link = None;
+    loop {
+      let mut block = [0; TAR_BLOCK];
+      if read_up_to(&mut self.source, &mut block)? < TAR_BLOCK || block.iter().all(|b| *b == 0) {
+        return Ok(None); // the end-of-archive blocks, or a tar cut short between entries
+      }
+      let header = Header::from_byte_slice(&block);
+      if !has_valid_checksum(header) {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "bad tar header checksum"));
+      }
+      let size = header.entry_size()?;
+      match header.entry_type() {
+        EntryType::GNULongName => long_name = Some(extension_name(&self.read_extension(size)?)),
+        EntryType::GNULongLink => long_link = Some(extension_name(&self.read_extension(size)?)),
+        EntryType::XHeader => {
+          let data = self.read_extension(size)?;
+          for extension in PaxExtensions::new(&data).flatten() {
+            let Ok(value) = extension.value() else {
+              continue;
+            };
+            match extension.key() {
+              Ok("path") => long_name = Some(value.to_owned()),
+              Ok("linkpath") => long_link = Some(value.to_owned()),
+              _ => {},
+            }
+          }
+        },
+        EntryType::XGlobalHeader => self.skip(size + block_padding(size))?,
+        entry_type => {
+          let mut pathname = long_name
+            .unwrap_or_else(|| String::from_utf8_lossy(&header.path_bytes()).into_owned());
+          if entry_type.is_dir() && !pathname.ends_with('/') {
+            pathname.push('/');
+          }
+          let link = long_link.or_else(|| {
+            header
+              .link_name_bytes()
+              .map(|link| String::from_utf8_lossy(&link).into_owned())
+          });
+          self.remaining = size;
+          self.padding = block_padding(size);
+          return Ok(Some(EntryHeader {
+            pathname,
+            mtime: header.mtime().unwrap_or(0) as i64,
+            symlink: link.clone().filter(|_| entry_type.is_symlink()),
+            hardlink: link.filter(|_| entry_type.is_hard_link()),
+          }));
+        },
+      }
+    }
+  }
+
+  fn read_chunk(&mut self) -> io::Result<Option<Vec<u8>>> {
+    if self.remaining == 0 {
+      return Ok(None);
+    }
+    let mut chunk = vec![0; self.remaining.min(BUFFER_SIZE as u64) as usize];
+    let read = self.source.read(&mut chunk)?;
+    if read == 0 {
+      return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "truncated tar entry"));
+    }
+    self.remaining -= read as u64;
+    chunk.truncate(read);
+    Ok(Some(chunk))
+  }
+}
+
+/// A zip entry, as listed in the central directory.
+struct ZipMember {
+  name: String,
+  data_start: u64,
+  compressed_size: u64,
+  method: CompressionMethod,
+  crc32: u32,
+  mtime: i64,
+  symlink: bool,
+}
+
+/// The data of the current zip entry, which owns the zip's source until the entry is done.
+enum ZipData<'a> {
+  Stored(io::Take<Box<dyn ReadSeek + 'a>>),
+  Deflated(DeflateDecoder<io::Take<Box<dyn ReadSeek + 'a>>>),
+}
+
+impl<'a> ZipData<'a> {
+  fn into_source(self) -> Box<dyn ReadSeek + 'a> {
+    match self {
+      ZipData::Stored(data) => data.into_inner(),
+      ZipData::Deflated(data) => data.into_inner().into_inner(),
+    }
+  }
+}
+
+impl Read for ZipData<'_> {
+  fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+    match self {
+      ZipData::Stored(data) => data.read(buf),
+      ZipData::Deflated(data) => data.read(buf),
+    }
+  }
+}
+
+struct ZipEntryData<'a> {
+  data: ZipData<'a>,
+  crc: Crc,
+  expected_crc: u32,
+  name: String,
+}
+
+struct ZipReader<'a> {
+  /// `None` while lent to the current entry's data
+  source: Option<Box<dyn ReadSeek + 'a>>,
+  members: vec::IntoIter<ZipMember>,
+  current: Option<ZipEntryData<'a>>,
+}
+
+fn zip_mtime(time: DateTime) -> i64 {
+  NaiveDate::from_ymd_opt(time.year() as i32, time.month() as u32, time.day() as u32)
+    .and_then(|date| {
+      date.and_hms_opt(time.hour() as u32, time.minute() as u32, time.second() as u32)
+    })
+    .map(|time| time.and_utc().timestamp())
+    .unwrap_or(0)
+}
+
+impl<'a> ZipReader<'a> {
+  fn new(source: Box<dyn ReadSeek + 'a>) -> io::Result<Self> {
+    let mut archive = ZipArchive::new(source)?;
+    let mut members = Vec::with_capacity(archive.len());
+    for index in 0..archive.len() {
+      let file = archive.by_index_raw(index)?;
+      members.push(ZipMember {
+        name: file.name().to_owned(),
+        data_start: file.data_start(),
+        compressed_size: file.compressed_size(),
+        method: file.compression(),
+        crc32: file.crc32(),
+        mtime: zip_mtime(file.last_modified()),
+        symlink: file
+          .unix_mode()
+          .is_some_and(|mode| mode & 0o170000 == 0o120000),
+      });
+    }
+    Ok(ZipReader {
+      source: Some(archive.into_inner()),
+      members: members.into_iter(),
+      current: None,
+    })
+  }
+
+  fn finish_current(&mut self) {
+    if let Some(current) = self.current.take() {
+      self.source = Some(current.data.into_source());
+    }
+  }
+}
+
+impl ArchiveReader for ZipReader<'_> {
+  fn next_entry(&mut self) -> io::Result<Option<EntryHeader>> {
+    self.finish_current();
+    let Some(member) = self.members.next() else {
+      return Ok(None);
+    };
+    let Some(mut source) = self.source.take() else {
+      return Err(io::Error::other("zip source lost"));
+    };
+    if let Err(e) = source.seek(SeekFrom::Start(member.data_start)) {
+      self.source = Some(source);
+      return Err(e);
+    }
+    let compressed = source.take(member.compressed_size);
+    let data = match member.method {
+      CompressionMethod::Stored => ZipData::Stored(compressed),
+      CompressionMethod::Deflated => ZipData::Deflated(DeflateDecoder::new(compressed)),
+      method => {
+        self.source = Some(compressed.into_inner());
+        return Err(io::Error::new(
+          io::ErrorKind::Unsupported,
+          format!("{method} compression of {:?}", member.name),
+        ));
+      },
+    };
+    self.current = Some(ZipEntryData {
+      data,
+      crc: Crc::new(),
+      expected_crc: member.crc32,
+      name: member.name.clone(),
+    });
+    let mut header = EntryHeader {
+      pathname: member.name,
+      mtime: member.mtime,
+      ..EntryHeader::default()
+    };
+    if member.symlink {
+      // a symlink's target is its data
+      let mut target = Vec::new();
+      while let Some(chunk) = self.read_chunk()? {
+        target.extend(chunk);
+      }
+      header.symlink = Some(String::from_utf8_lossy(&target).into_owned());
+    }
+    Ok(Some(header))
+  }
+
+  fn read_chunk(&mut self) -> io::Result<Option<Vec<u8>>> {
+    let Some(current) = self.current.as_mut() else {
+      return Ok(None);
+    };
+    let mut chunk = vec![0; BUFFER_SIZE];
+    let read = match current.data.read(&mut chunk) {
+      Ok(read) => read,
+      Err(e) => {
+        self.finish_current();
+        return Err(e);
+      },
+    };
+    if read == 0 {
+      let crc_matches = current.crc.sum() == current.expected_crc;
+      let name = current.name.clone();
+      self.finish_current();
+      if !crc_matches {
+        return Err(io::Error::new(
+          io::ErrorKind::InvalidData,
+          format!("CRC mismatch in {name:?}"),
+        ));
+      }
+      return Ok(None);
+    }
+    chunk.truncate(read);
+    current.crc.update(&chunk);
+    Ok(Some(chunk))
+  }
+}
+
+struct PureZipWriter {
+  zip: ZipWriter<File>,
+}
+
+impl ArchiveWriter for PureZipWriter {
+  fn write_entry(&mut self, path: &str, data: Vec<u8>) -> io::Result<()> {
+    // a fixed timestamp, so that rewrites of the same sources are byte-identical
+    let options = FileOptions::default()
+      .compression_method(CompressionMethod::Deflated)
+      .last_modified_time(DateTime::default())
+      .unix_permissions(0o644);
+    self.zip.start_file(path, options)?;
+    self.zip.write_all(&data)
+  }
+}
+
+struct PureTarWriter<W: Write> {
+  builder: tar::Builder<W>,
+}
+
+impl<W: Write> ArchiveWriter for PureTarWriter<W> {
+  fn write_entry(&mut self, path: &str, data: Vec<u8>) -> io::Result<()> {
+    let mut header = Header::new_gnu();
+    header.set_entry_type(EntryType::Regular);
+    header.set_size(data.len() as u64);
+    header.set_mode(0o644);
+    header.set_mtime(0);
+    self.builder.append_data(&mut header, path, data.as_slice())
+  }
+}
@@ -0,0 +1,217 @@
+//! Ingestion of arXiv's bulk source dumps, the monthly `arXiv_src_YYMM_NNN.tar` bundles.
+//!
+//! Each bundle holds one member per paper, `YYMM/{id}.gz` (or `.pdf` for PDF-only papers),
+//! with old-style ids flattened as in `0001/astro-ph0001001.gz`. Members are repackaged into
+//! the usual corpus layout, just as if they had been downloaded from `e-print/{id}`.
+use std::fs;
+use std::path::Path;
+use std::time::{Duration, UNIX_EPOCH};
+
+use rayon::prelude::*;
+use serde::Serialize;
+use tracing::{info_span, warn};
+
+use super::archive::{backend, ReadMode};
+use super::{
+  arxiv_id_of, corpus_paths, record_non_tex, repackage_arxiv_download_with_options,
+  RepackageOptions, NON_TEX_IDS_FILEPATH,
+};
+use crate::error::{Ar5ivError, Result};
+
+/// Bundles fully ingested so far, one file name per line
+pub const INGESTED_BUNDLES_FILEPATH: &str = "ingested_bundles.txt";
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct BundleSummary {
+  pub bundle: String,
+  pub members: usize,
+  pub repackaged: usize,
+  /// Members not newer than the paper's local sources
+  pub skipped: usize,
+  pub failed: usize,
+}
+
+/// When a bundle's papers were submitted, as (year, month, sequence number), e.g.
+/// `(1999, 12, 1)` for `arXiv_src_9912_001.tar`. Ids of the 1990s carry two-digit years too.
+pub fn bundle_order(file_name: &str) -> Option<(u32, u32, u32)> {
+  let stem = file_name.strip_prefix("arXiv_src_")?.strip_suffix(".tar")?;
+  let (yymm, sequence) = stem.split_once('_')?;
+  if yymm.len() != 4 {
+    return None;
+  }
+  let year: u32 = yymm[..2].parse().ok()?;
+  let month = yymm[2..].parse().ok()?;
+  let century = if year >= 91 { 1900 } else { 2000 };
+  Some((century + year, month, sequence.parse().ok()?))
+}
+
+/// The arXiv id of a bundle member, e.g. `astro-ph/0001001` for `0001/astro-ph0001001.gz`.
+pub fn bundle_member_id(member_path: &str) -> Option<String> {
+  let file_name = member_path.rsplit('/').next()?;
+  let stem = file_name
+    .strip_suffix(".gz")
+    .or_else(|| file_name.strip_suffix(".pdf"))?;
+  Some(arxiv_id_of(stem))
+}
+
+/// Whether the local output for a paper is at least as recent as a member's `mtime`.
+fn local_is_current(output_path: &str, mtime: i64) -> bool {
+  let Ok(modified) = fs::metadata(output_path).and_then(|meta| meta.modified()) else {
+    return false;
+  };
+  modified >= UNIX_EPOCH + Duration::from_secs(mtime.max(0) as u64)
+}
+
+/// Members read ahead of repackaging, up to this many bytes at once
+const BATCH_BYTES: usize = 256 * 1024 * 1024;
+
+/// A member read out of a bundle, waiting to be repackaged.
+struct Member {
+  arxiv_id: String,
+  member_path: String,
+  to_dir: String,
+  base_name: String,
+  data: Vec<u8>,
+}
+
+/// Repackage every paper in a bundle into the corpus. With `only_newer`, papers whose local
+/// sources are at least as recent as their member are left alone. Member names carry no
+/// version, and older bundles hold older versions than the latest one `check_arxiv_versions`
+/// found, so retained versions are numbered in order of arrival.
+/// The bundle is read in order, and its members repackaged in parallel, a batch at a time.
+/// Failures for single papers are counted and logged; a bundle that does not read through
+/// and corpus I/O errors end the bundle.
+pub fn ingest_bundle(
+  bundle_path: &Path,
+  options: &RepackageOptions,
+  only_newer: bool,
+) -> Result<BundleSummary> {
+  let bundle = bundle_path.display().to_string();
+  let _span = info_span!("ingest_bundle", bundle = %bundle).entered();
+  let mut summary = BundleSummary {
+    bundle: bundle.clone(),
+    ..BundleSummary::default()
+  };
+  let mut reader = backend()
+    .open_file(bundle_path, ReadMode::Archive)
+    .map_err(|e| Ar5ivError::archive(&bundle, format!("does not open: {e}")))?;
+  let unreadable = |e| Ar5ivError::archive(&bundle, format!("does not read through: {e}"));
+  let mut batch = Vec::new();
+  let mut batch_bytes = 0;
+  while let Some(member) = reader.next_entry().map_err(unreadable)? {
+    let member_path = member.pathname;
+    let Some(arxiv_id) = bundle_member_id(&member_path) else {
+      continue; // directories, and the occasional stray file
+    };
+    summary.members += 1;
+    let (to_dir, base_name) = match corpus_paths(&arxiv_id) {
+      Ok(paths) => paths,
+      Err(e) => {
+        warn!(member = %member_path, error = %e, "not a paper, skipped");
+        summary.failed += 1;
+        continue;
+      },
+    };
+    let output_path = options.format.output_path(&to_dir, &base_name);
+    if only_newer && local_is_current(&output_path, member.mtime) {
+      summary.skipped += 1;
+      continue;
+    }
+    let mut data = Vec::new();
+    while let Some(chunk) = reader.read_chunk().map_err(unreadable)? {
+      data.extend(chunk);
+    }
+    // the later member of a paper must not race the earlier one
+    if batch.iter().any(|queued: &Member| queued.arxiv_id == arxiv_id) {
+      repackage_batch(std::mem::take(&mut batch), options, &mut summary)?;
+      batch_bytes = 0;
+    }
+    batch_bytes += data.len();
+    batch.push(Member {
+      arxiv_id,
+      member_path,
+      to_dir,
+      base_name,
+      data,
+    });
+    if batch_bytes >= BATCH_BYTES {
+      repackage_batch(std::mem::take(&mut batch), options, &mut summary)?;
+      batch_bytes = 0;
+    }
+  }
+  repackage_batch(batch, options, &mut summary)?;
+  Ok(summary)
+}
+
+fn repackage_batch(
+  batch: Vec<Member>,
+  options: &RepackageOptions,
+  summary: &mut BundleSummary,
+) -> Result<()> {
+  let results: Vec<Result<()>> = batch
+    .into_par_iter()
+    .map(|mut member| {
+      let member_options = RepackageOptions {
+        filename_hint: member.member_path.rsplit('/').next().map(str::to_owned),
+        ..options.clone()
+      };
+      let report = repackage_arxiv_download_with_options(
+        &mut member.data,
+        member.to_dir,
+        member.base_name,
+        &member_options,
+      )
+      .map_err(Ar5ivError::from)
+      .inspect_err(|e| warn!(member = %member.member_path, error = %e, "repackaging failed"))?;
+      // PDF-only and HTML submissions are kept, but not sent to conversion
+      if !report.class.is_tex() && !report.unchanged {
+        record_non_tex(&member.arxiv_id, report.class, NON_TEX_IDS_FILEPATH)?;
+      }
+      Ok(())
+    })
+    .collect();
+  // count the whole batch before giving up on a fatal error
+  let mut fatal = None;
+  for result in results {
+    match result {
+      Ok(()) => summary.repackaged += 1,
+      Err(e) => {
+        summary.failed += 1;
+        if e.is_fatal() {
+          fatal = fatal.or(Some(e));
+        }
+      },
+    }
+  }
+  fatal.map_or(Ok(()), Err)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn bundles_order_by_date() {
+    let mut names = [
+      "arXiv_src_0001_001.tar",
+      "arXiv_src_1001_010.tar",
+      "arXiv_src_9912_002.tar",
+      "arXiv_src_1001_002.tar",
+      "arXiv_src_9108_001.tar",
+      "arXiv_src_9912_001.tar",
+    ];
+    names.sort_by_key(|name| bundle_order(name).unwrap());
+    assert_eq!(
+      names,
+      [
+        "arXiv_src_9108_001.tar",
+        "arXiv_src_9912_001.tar",
+        "arXiv_src_9912_002.tar",
+        "arXiv_src_0001_001.tar",
+        "arXiv_src_1001_002.tar",
+        "arXiv_src_1001_010.tar",
+      ]
+    );
+    assert_eq!(bundle_order("arXiv_src_manifest.xml"), None);
+  }
+}
@@ -0,0 +1,151 @@
+//! Content sniffing for e-print payloads.
+//!
+//! Besides TeX sources, arXiv's e-print endpoint serves PDFs for PDF-only submissions, HTML
+//! bundles for HTML submissions, and the occasional PostScript or DOCX upload. Only TeX is
+//! worth sending to conversion, the rest is kept under its proper extension and reported.
+use std::path::Path;
+
+use serde::Serialize;
+
+/// How much of a file's head is looked at when sniffing
+pub(super) const SNIFF_LEN: usize = 4096;
+
+const TEX_MARKERS: [&str; 8] = [
+  "\\documentclass",
+  "\\documentstyle",
+  "\\begin{",
+  "\\input",
+  "\\def",
+  "\\section",
+  "\\title",
+  "\\bye",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SubmissionClass {
+  Tex,
+  Pdf,
+  PostScript,
+  Html,
+  Docx,
+  /// Binary content we don't recognize
+  Unknown,
+}
+
+impl SubmissionClass {
+  /// The extension a single file of this class is stored under.
+  pub fn extension(&self) -> &'static str {
+    match self {
+      SubmissionClass::Tex => "tex",
+      SubmissionClass::Pdf => "pdf",
+      SubmissionClass::PostScript => "ps",
+      SubmissionClass::Html => "html",
+      SubmissionClass::Docx => "docx",
+      SubmissionClass::Unknown => "bin",
+    }
+  }
+
+  pub fn is_tex(&self) -> bool { *self == SubmissionClass::Tex }
+
+  pub fn as_str(&self) -> &'static str {
+    match self {
+      SubmissionClass::Tex => "tex",
+      SubmissionClass::Pdf => "pdf",
+      SubmissionClass::PostScript => "postscript",
+      SubmissionClass::Html => "html",
+      SubmissionClass::Docx => "docx",
+      SubmissionClass::Unknown => "unknown",
+    }
+  }
+}
+
+/// Classify by magic bytes alone, `None` if they are not conclusive.
+pub fn sniff_magic(data: &[u8]) -> Option<SubmissionClass> {
+  let head = &data[..data.len().min(SNIFF_LEN)];
+  if head.starts_with(b"%PDF-") {
+    Some(SubmissionClass::Pdf)
+  } else if head.starts_with(b"%!PS") || head.starts_with(&[0xc5, 0xd0, 0xd3, 0xc6]) {
+    Some(SubmissionClass::PostScript)
+  } else if head.starts_with(b"PK\x03\x04") && contains(head, b"word/") {
+    Some(SubmissionClass::Docx)
+  } else if looks_like_html(head) {
+    Some(SubmissionClass::Html)
+  } else {
+    None
+  }
+}
+
+/// Classify by file name extension, `None` for names that say nothing, e.g. `.gz`.
+pub fn class_from_filename(name: &str) -> Option<SubmissionClass> {
+  let extension = Path::new(name).extension()?.to_str()?.to_ascii_lowercase();
+  match extension.as_str() {
+    "tex" | "ltx" | "latex" | "sty" | "cls" => Some(SubmissionClass::Tex),
+    "pdf" => Some(SubmissionClass::Pdf),
+    "ps" | "eps" => Some(SubmissionClass::PostScript),
+    "html" | "htm" => Some(SubmissionClass::Html),
+    "docx" => Some(SubmissionClass::Docx),
+    _ => None,
+  }
+}
+
+/// Classify a payload that is a single file: magic bytes first, then the file name arXiv
+/// served it under, then a look for TeX markup. Text without markup is still taken as TeX,
+/// as plain TeX submissions need not use any of the usual commands.
+pub fn classify_single_file(data: &[u8], filename_hint: Option<&str>) -> SubmissionClass {
+  sniff_magic(data)
+    .or_else(|| filename_hint.and_then(class_from_filename))
+    .unwrap_or_else(|| {
+      let head = &data[..data.len().min(SNIFF_LEN)];
+      if looks_like_tex(head) || !head.contains(&0) {
+        SubmissionClass::Tex
+      } else {
+        SubmissionClass::Unknown
+      }
+    })
+}
+
+/// Classify a multi-file submission from its members' paths and contents.
+/// Any TeX source makes it a TeX submission, whatever figures or PDFs come along with it.
+pub fn classify_members<'a>(members: impl Iterator<Item = (&'a str, &'a [u8])>) -> SubmissionClass {
+  let mut found = Vec::new();
+  for (path, data) in members {
+    if path == "word/document.xml" {
+      found.push(SubmissionClass::Docx);
+      continue;
+    }
+    let class = class_from_filename(path).or_else(|| sniff_magic(data)).or_else(|| {
+      let head = &data[..data.len().min(SNIFF_LEN)];
+      looks_like_tex(head).then_some(SubmissionClass::Tex)
+    });
+    match class {
+      Some(SubmissionClass::Tex) => return SubmissionClass::Tex,
+      Some(class) => found.push(class),
+      None => {},
+    }
+  }
+  [
+    SubmissionClass::Docx,
+    SubmissionClass::Html,
+    SubmissionClass::Pdf,
+    SubmissionClass::PostScript,
+  ]
+  .into_iter()
+  .find(|class| found.contains(class))
+  .unwrap_or(SubmissionClass::Unknown)
+}
+
+fn looks_like_tex(head: &[u8]) -> bool {
+  let text = String::from_utf8_lossy(head);
+  TEX_MARKERS.iter().any(|marker| text.contains(marker))
+}
+
+fn looks_like_html(head: &[u8]) -> bool {
+  let text = String::from_utf8_lossy(head);
+  let start = text.trim_start_matches('\u{feff}').trim_start().to_ascii_lowercase();
+  start.starts_with("<!doctype html") || start.starts_with("<html")
+}
+
+fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+  haystack.windows(needle.len()).any(|window| window == needle)
+}
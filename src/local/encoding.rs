@@ -0,0 +1,72 @@
+//! Text encoding detection for single-file TeX submissions.
+//!
+//! Old single-file submissions are often Latin-1, KOI8-R or similar, which converters expecting
+//! UTF-8 choke on. The encoding is guessed with chardetng, and the file optionally re-encoded,
+//! with its original kept next to it as `{name}.orig`.
+use std::env;
+
+use chardetng::EncodingDetector;
+use encoding_rs::{Encoding, UTF_8};
+use lazy_static::lazy_static;
+use regex::Regex;
+use serde::Serialize;
+
+/// Appended to the name of a re-encoded file, for its original bytes
+pub const ORIGINAL_SUFFIX: &str = ".orig";
+/// `1` or `true` to re-encode single-file TeX submissions in legacy encodings to UTF-8
+pub const NORMALIZE_ENCODING_ENV: &str = "AR5IV_NORMALIZE_ENCODING";
+
+lazy_static! {
+  static ref INPUTENC_REGEX: Regex =
+    Regex::new(r"(\\usepackage\s*)\[[^\]]*\](\s*\{inputenc\})").unwrap();
+  static ref INPUTENCODING_REGEX: Regex = Regex::new(r"\\inputencoding\s*\{[^}]*\}").unwrap();
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct TextEncoding {
+  /// The WHATWG name of the detected encoding, e.g. `UTF-8` or `windows-1252`
+  pub detected: String,
+  /// Whether the file was re-encoded to UTF-8
+  pub normalized: bool,
+  /// Where the original bytes of a re-encoded file were kept
+  pub original: Option<String>,
+}
+
+/// Whether re-encoding is switched on via `AR5IV_NORMALIZE_ENCODING`, off if unset.
+pub fn normalize_from_env() -> bool {
+  env::var(NORMALIZE_ENCODING_ENV)
+    .is_ok_and(|normalize| normalize == "1" || normalize.eq_ignore_ascii_case("true"))
+}
+
+/// Guess the encoding of a text file; valid UTF-8 (including plain ASCII) is taken as such.
+pub fn detect_encoding(data: &[u8]) -> &'static Encoding {
+  if std::str::from_utf8(data).is_ok() {
+    return UTF_8;
+  }
+  let mut detector = EncodingDetector::new();
+  detector.feed(data, true);
+  detector.guess(None, true)
+}
+
+/// Re-encode `data` from `encoding` to UTF-8, declaring it as such to `inputenc`, and note the
+/// conversion in a TeX comment. A `%&format` first line stays first, as TeX only looks there.
+pub fn normalize_to_utf8(data: &[u8], encoding: &'static Encoding) -> Vec<u8> {
+  let (text, _, _) = encoding.decode(data);
+  let text = INPUTENC_REGEX.replace_all(&text, "${1}[utf8]${2}");
+  let text = INPUTENCODING_REGEX.replace_all(&text, "\\inputencoding{utf8}");
+  let note = format!("%% ar5iv: re-encoded from {} to UTF-8\n", encoding.name());
+  let format_line_end = if text.starts_with("%&") {
+    text.find('\n').map_or(text.len(), |newline| newline + 1)
+  } else {
+    0
+  };
+  let (format_line, rest) = text.split_at(format_line_end);
+  let mut normalized = String::with_capacity(text.len() + note.len() + 1);
+  normalized.push_str(format_line);
+  if !format_line.is_empty() && !format_line.ends_with('\n') {
+    normalized.push('\n');
+  }
+  normalized.push_str(&note);
+  normalized.push_str(rest);
+  normalized.into_bytes()
+}
@@ -0,0 +1,71 @@
+//! Content fingerprints of repackaged zips, to tell real source changes from re-downloads.
+//!
+//! The fingerprint covers what LaTeXML gets to see, the entries' paths and data in path order,
+//! and none of the zip-level metadata. It is kept in a `{id}.sha256` sidecar next to `{id}.zip`.
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use sha2::digest::Output;
+use sha2::{Digest, Sha256};
+
+use super::archive::{backend, ReadMode};
+use super::output::read_output_entries;
+use super::RepackageError;
+
+pub(super) fn hash_sidecar(to_dir: impl AsRef<Path>, base_name: &str) -> PathBuf {
+  to_dir.as_ref().join(format!("{base_name}.sha256"))
+}
+
+/// Read an archive (or an output directory) through, returning its number of files
+/// and its content fingerprint, as lowercase hex.
+pub fn content_hash(archive_path: &str) -> Result<(usize, String), RepackageError> {
+  if Path::new(archive_path).is_dir() {
+    let entries = read_output_entries(archive_path)?;
+    let entry_digests = entries
+      .iter()
+      .map(|(path, data)| (path.clone(), Sha256::digest(data)))
+      .collect();
+    return Ok((entries.len(), combine(&entry_digests)));
+  }
+  let mut reader = backend()
+    .open_file(Path::new(archive_path), ReadMode::Archive)
+    .map_err(|e| RepackageError::archive(archive_path, format!("does not open: {e}")))?;
+  let unreadable =
+    |e| RepackageError::archive(archive_path, format!("does not read through: {e}"));
+  let mut entry_digests = BTreeMap::new();
+  let mut entries = 0;
+  while let Some(entry) = reader.next_entry().map_err(unreadable)? {
+    if entry.pathname.ends_with('/') {
+      continue; // directory entries carry no content, and an extracted output has none
+    }
+    entries += 1;
+    let mut hasher = Sha256::new();
+    while let Some(chunk) = reader.read_chunk().map_err(unreadable)? {
+      hasher.update(&chunk);
+    }
+    entry_digests.insert(entry.pathname, hasher.finalize());
+  }
+  Ok((entries, combine(&entry_digests)))
+}
+
+pub(super) fn combine(entry_digests: &BTreeMap<String, Output<Sha256>>) -> String {
+  let mut hasher = Sha256::new();
+  for (path, digest) in entry_digests.iter() {
+    hasher.update(path.as_bytes());
+    hasher.update([0]);
+    hasher.update(digest);
+  }
+  format!("{:x}", hasher.finalize())
+}
+
+/// The recorded fingerprint of the canonical `{id}.zip`, if any.
+pub fn stored_hash(to_dir: &str, base_name: &str) -> Option<String> {
+  let hash = fs::read_to_string(hash_sidecar(to_dir, base_name)).ok()?;
+  Some(hash.trim().to_owned()).filter(|hash| !hash.is_empty())
+}
+
+pub(crate) fn record_hash(to_dir: &str, base_name: &str, hash: &str) -> io::Result<()> {
+  fs::write(hash_sidecar(to_dir, base_name), format!("{hash}\n"))
+}
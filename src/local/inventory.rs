@@ -0,0 +1,157 @@
+//! An incremental inventory of the corpus: its paper directories and their mtimes, kept in an
+//! index file, so that every scan can tell which papers were added, changed or removed since
+//! the previous one.
+//!
+//! A paper is taken as changed when the mtime of its `{id}.sha256` fingerprint sidecar moves,
+//! which only a repackaging that changed the sources rewrites. A re-download with the same
+//! contents leaves it alone, though it still touches the paper directory along the way. Papers
+//! without a sidecar fall back to the mtime of their directory.
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{self, prelude::*, BufReader, BufWriter};
+use std::path::Path;
+use std::time::UNIX_EPOCH;
+
+use jwalk::WalkDir;
+use serde::Serialize;
+use tracing::warn;
+
+use super::arxiv_id_of;
+use super::fingerprint::hash_sidecar;
+use crate::error::{io_at, Result};
+
+/// One `{yymm}/{base_name}\t{mtime}` line per paper directory
+pub const CORPUS_INDEX_FILEPATH: &str = "corpus_index.tsv";
+
+/// Paper directories, as `{yymm}/{base_name}` relative to the corpus root, with the mtimes of
+/// their fingerprints in nanoseconds since the epoch.
+pub type CorpusIndex = BTreeMap<String, u128>;
+
+/// What changed between two scans, as arXiv ids.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct ScanDelta {
+  pub added: Vec<String>,
+  pub changed: Vec<String>,
+  pub removed: Vec<String>,
+}
+
+impl ScanDelta {
+  pub fn is_empty(&self) -> bool {
+    self.added.is_empty() && self.changed.is_empty() && self.removed.is_empty()
+  }
+}
+
+/// The arXiv id of a paper in the index.
+pub fn index_key_id(key: &str) -> String { arxiv_id_of(key.rsplit('/').next().unwrap_or(key)) }
+
+/// Read the index, which is empty before the first scan.
+pub fn load_index(index_filepath: &str) -> Result<CorpusIndex> {
+  let file = match File::open(index_filepath) {
+    Ok(file) => file,
+    Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(CorpusIndex::new()),
+    Err(e) => return Err(io_at(index_filepath)(e)),
+  };
+  let mut index = CorpusIndex::new();
+  for line in BufReader::new(file).lines() {
+    let line = line.map_err(io_at(index_filepath))?;
+    match line
+      .split_once('\t')
+      .and_then(|(key, mtime)| Some((key, mtime.parse().ok()?)))
+    {
+      Some((key, mtime)) => {
+        index.insert(key.to_owned(), mtime);
+      },
+      // the paper turns up as added on the next scan
+      None => warn!(line, "skipping malformed index line"),
+    }
+  }
+  Ok(index)
+}
+
+/// Write the index to a temporary file first, so that an interrupted scan keeps the old one.
+pub fn save_index(index_filepath: &str, index: &CorpusIndex) -> Result<()> {
+  let tmp_filepath = format!("{index_filepath}.tmp");
+  let mut tmp_file = BufWriter::new(File::create(&tmp_filepath).map_err(io_at(&tmp_filepath))?);
+  for (key, mtime) in index.iter() {
+    writeln!(tmp_file, "{key}\t{mtime}").map_err(io_at(&tmp_filepath))?;
+  }
+  tmp_file
+    .into_inner()
+    .map_err(|e| e.into_error())
+    .and_then(|file| file.sync_all())
+    .map_err(io_at(&tmp_filepath))?;
+  fs::rename(&tmp_filepath, index_filepath).map_err(io_at(index_filepath))
+}
+
+/// Walk the corpus, whose paper directories sit at `{root}/{yymm}/{id}`.
+pub fn walk_corpus(root_path: &str) -> CorpusIndex {
+  let mut index = CorpusIndex::new();
+  for entry in WalkDir::new(root_path)
+    .follow_links(true)
+    .max_depth(2)
+    .min_depth(2)
+    .into_iter()
+    .flatten()
+    .filter(|entry| entry.file_type().is_dir())
+  {
+    let path = entry.path();
+    let Some(key) = path
+      .strip_prefix(root_path)
+      .ok()
+      .and_then(|relative| relative.to_str())
+    else {
+      warn!(path = ?path, "skipping non-UTF-8 corpus entry");
+      continue;
+    };
+    let base_name = entry.file_name().to_string_lossy();
+    let mtime = mtime_nanos(&hash_sidecar(&path, &base_name))
+      .or_else(|| mtime_nanos(&path))
+      .unwrap_or(0);
+    index.insert(key.to_owned(), mtime);
+  }
+  index
+}
+
+fn mtime_nanos(path: &Path) -> Option<u128> {
+  fs::metadata(path)
+    .and_then(|meta| meta.modified())
+    .ok()
+    .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+    .map(|since_epoch| since_epoch.as_nanos())
+}
+
+/// The papers added, changed or removed going from index `old` to `new`.
+pub fn diff_index(old: &CorpusIndex, new: &CorpusIndex) -> ScanDelta {
+  let mut delta = ScanDelta::default();
+  for (key, mtime) in new.iter() {
+    match old.get(key) {
+      None => delta.added.push(index_key_id(key)),
+      Some(old_mtime) if old_mtime != mtime => delta.changed.push(index_key_id(key)),
+      Some(_) => {},
+    }
+  }
+  delta.removed = old
+    .keys()
+    .filter(|key| !new.contains_key(*key))
+    .map(|key| index_key_id(key))
+    .collect();
+  delta
+}
+
+/// Scan the corpus against the index at `index_filepath`, and bring the index up to date.
+/// Returns the new index and what changed since the previous scan.
+pub fn scan_corpus(root_path: &str, index_filepath: &str) -> Result<(CorpusIndex, ScanDelta)> {
+  let old = load_index(index_filepath)?;
+  let root = Path::new(root_path);
+  if !root.is_dir() {
+    // a missing (unmounted?) corpus would otherwise read as every paper removed
+    return Err(io_at(root_path)(io::Error::new(
+      io::ErrorKind::NotFound,
+      "corpus root is not a directory",
+    )));
+  }
+  let new = walk_corpus(root_path);
+  let delta = diff_index(&old, &new);
+  save_index(index_filepath, &new)?;
+  Ok((new, delta))
+}
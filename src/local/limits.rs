@@ -0,0 +1,168 @@
+//! Decompression-bomb limits, enforced while an e-print is being read.
+use std::env;
+use std::fs;
+use std::path::Path;
+
+use thiserror::Error;
+
+use super::Payload;
+
+/// Most uncompressed bytes a single e-print may expand to
+pub const MAX_TOTAL_BYTES_ENV: &str = "AR5IV_MAX_TOTAL_BYTES";
+/// Most uncompressed bytes of a single entry
+pub const MAX_ENTRY_BYTES_ENV: &str = "AR5IV_MAX_ENTRY_BYTES";
+/// Most entries in a single e-print
+pub const MAX_ENTRIES_ENV: &str = "AR5IV_MAX_ENTRIES";
+/// Most uncompressed bytes per byte of payload
+pub const MAX_COMPRESSION_RATIO_ENV: &str = "AR5IV_MAX_COMPRESSION_RATIO";
+/// Uncompressed bytes up to which the compression ratio goes unchecked
+pub const MIN_RATIO_CHECKED_BYTES_ENV: &str = "AR5IV_MIN_RATIO_CHECKED_BYTES";
+/// Most payload bytes spooled from a `Read` source
+pub const MAX_PAYLOAD_BYTES_ENV: &str = "AR5IV_MAX_PAYLOAD_BYTES";
+
+/// Caps on what a single e-print may expand to. The defaults are well above any legitimate
+/// submission (arXiv itself caps uploads at 50MB), while keeping a bomb from filling the disk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RepackageLimits {
+  /// Also the memory budget of each repackaging worker: a multi-file e-print is held in memory
+  /// whole before it is written out, and the copies made for links, as well as the contents of
+  /// expanded nested archives, count towards it alongside the entries themselves. Parallel
+  /// workers may take up to this much each.
+  pub max_total_bytes: u64,
+  pub max_entry_bytes: u64,
+  pub max_entries: usize,
+  /// Uncompressed bytes per byte of payload
+  pub max_compression_ratio: u64,
+  /// Uncompressed bytes up to which the ratio goes unchecked: repetitive TeX compresses
+  /// extremely well, and a small expansion is harmless at any ratio
+  pub min_ratio_checked_bytes: u64,
+  /// Most payload bytes to spool from a `Read` source, before anything is unpacked
+  pub max_payload_bytes: u64,
+}
+
+impl Default for RepackageLimits {
+  fn default() -> Self {
+    RepackageLimits {
+      max_total_bytes: 256 << 20,
+      max_entry_bytes: 128 << 20,
+      max_entries: 20_000,
+      max_compression_ratio: 200,
+      min_ratio_checked_bytes: 10 << 20,
+      max_payload_bytes: 1 << 30,
+    }
+  }
+}
+
+fn parsed_env<T: std::str::FromStr>(name: &str) -> Option<T> {
+  env::var(name).ok().and_then(|value| value.parse().ok())
+}
+
+impl RepackageLimits {
+  /// The defaults, with any of them overridden via `AR5IV_MAX_TOTAL_BYTES`,
+  /// `AR5IV_MAX_ENTRY_BYTES`, `AR5IV_MAX_ENTRIES`, `AR5IV_MAX_COMPRESSION_RATIO`,
+  /// `AR5IV_MIN_RATIO_CHECKED_BYTES` and `AR5IV_MAX_PAYLOAD_BYTES`.
+  pub fn from_env() -> Self {
+    let defaults = RepackageLimits::default();
+    RepackageLimits {
+      max_total_bytes: parsed_env(MAX_TOTAL_BYTES_ENV).unwrap_or(defaults.max_total_bytes),
+      max_entry_bytes: parsed_env(MAX_ENTRY_BYTES_ENV).unwrap_or(defaults.max_entry_bytes),
+      max_entries: parsed_env(MAX_ENTRIES_ENV).unwrap_or(defaults.max_entries),
+      max_compression_ratio: parsed_env(MAX_COMPRESSION_RATIO_ENV)
+        .unwrap_or(defaults.max_compression_ratio),
+      min_ratio_checked_bytes: parsed_env(MIN_RATIO_CHECKED_BYTES_ENV)
+        .unwrap_or(defaults.min_ratio_checked_bytes),
+      max_payload_bytes: parsed_env(MAX_PAYLOAD_BYTES_ENV).unwrap_or(defaults.max_payload_bytes),
+    }
+  }
+}
+
+#[derive(Debug, Clone, Error, PartialEq, Eq)]
+pub enum LimitExceeded {
+  #[error("more than {0} entries")]
+  Entries(usize),
+  #[error("more than {0} uncompressed bytes in total")]
+  TotalBytes(u64),
+  #[error("an entry of more than {0} uncompressed bytes")]
+  EntryBytes(u64),
+  #[error("a compression ratio above {0}")]
+  CompressionRatio(u64),
+  #[error("a payload of more than {0} bytes")]
+  PayloadBytes(u64),
+}
+
+/// Running totals for one e-print, checked against the limits as data streams in.
+#[derive(Debug)]
+pub struct LimitTracker {
+  limits: RepackageLimits,
+  compressed_bytes: u64,
+  total_bytes: u64,
+  entries: usize,
+  entry_bytes: u64,
+}
+
+impl LimitTracker {
+  pub fn new(limits: RepackageLimits, compressed_bytes: u64) -> Self {
+    LimitTracker {
+      limits,
+      compressed_bytes,
+      total_bytes: 0,
+      entries: 0,
+      entry_bytes: 0,
+    }
+  }
+
+  pub fn start_entry(&mut self) -> Result<(), LimitExceeded> {
+    self.entries += 1;
+    self.entry_bytes = 0;
+    if self.entries > self.limits.max_entries {
+      return Err(LimitExceeded::Entries(self.limits.max_entries));
+    }
+    Ok(())
+  }
+
+  /// Account for `len` more uncompressed bytes of the current entry.
+  pub fn add_bytes(&mut self, len: usize) -> Result<(), LimitExceeded> {
+    self.entry_bytes += len as u64;
+    if self.entry_bytes > self.limits.max_entry_bytes {
+      return Err(LimitExceeded::EntryBytes(self.limits.max_entry_bytes));
+    }
+    self.add_copy(len)
+  }
+
+  /// Account for `len` uncompressed bytes copied from an entry already read, as for a resolved
+  /// link. Only the totals grow, the copied entry having passed the per-entry cap already.
+  pub fn add_copy(&mut self, len: usize) -> Result<(), LimitExceeded> {
+    self.total_bytes += len as u64;
+    if self.total_bytes > self.limits.max_total_bytes {
+      Err(LimitExceeded::TotalBytes(self.limits.max_total_bytes))
+    } else if self.total_bytes > self.limits.min_ratio_checked_bytes
+      && self.total_bytes
+        > self
+          .compressed_bytes
+          .max(1)
+          .saturating_mul(self.limits.max_compression_ratio)
+    {
+      Err(LimitExceeded::CompressionRatio(self.limits.max_compression_ratio))
+    } else {
+      Ok(())
+    }
+  }
+}
+
+/// Set an over-limit payload aside as `{quarantine_dir}/{base_name}.payload`, with the reason
+/// next to it, for later inspection. Returns the payload's path.
+pub(super) fn quarantine(
+  payload: &Payload,
+  quarantine_dir: &str,
+  base_name: &str,
+  reason: &LimitExceeded,
+) -> std::io::Result<String> {
+  fs::create_dir_all(quarantine_dir)?;
+  let payload_path = Path::new(quarantine_dir).join(format!("{base_name}.payload"));
+  payload.save_to(&payload_path)?;
+  fs::write(
+    Path::new(quarantine_dir).join(format!("{base_name}.reason.txt")),
+    format!("{reason}\n"),
+  )?;
+  Ok(payload_path.display().to_string())
+}
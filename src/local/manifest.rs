@@ -0,0 +1,255 @@
+//! A per-paper `manifest.json`: the main TeX file, the files by role, dangling `\input`s
+//! and stripped files.
+//!
+//! The main file is, in order of preference: the `00README` top-level file; the one TeX file
+//! with a preamble that no other file inputs; among several, one named by convention
+//! (`main.tex`, `ms.tex`, ...), at the top level, or the largest.
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use lazy_static::lazy_static;
+use regex::Regex;
+use serde::Serialize;
+
+use super::encoding::TextEncoding;
+use super::classify::classify_members;
+use super::readme::{find_directives, SubmissionDirectives};
+use super::sanitize::normalize_entry_path;
+use super::strip::StrippedEntry;
+
+/// Written next to `{id}.zip` in the paper directory
+pub const MANIFEST_FILENAME: &str = "manifest.json";
+const CONVENTIONAL_MAIN_NAMES: [&str; 5] = ["main", "ms", "paper", "article", "manuscript"];
+
+lazy_static! {
+  static ref INPUT_REGEX: Regex = Regex::new(
+    r"\\(?:input|include|subfile|InputIfFileExists)\s*\{([^}]+)\}|\\input\s+([^\s{}\\]+)"
+  )
+  .unwrap();
+  static ref COMMENT_REGEX: Regex = Regex::new(r"(?m)(^|[^\\])%.*$").unwrap();
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FileRole {
+  Tex,
+  Bib,
+  Bbl,
+  /// Classes, packages and bibliography styles, with their definition files
+  Style,
+  Figure,
+  Other,
+}
+
+impl FileRole {
+  pub fn of(path: &str) -> FileRole {
+    let extension = Path::new(path)
+      .extension()
+      .and_then(|extension| extension.to_str())
+      .map(|extension| extension.to_ascii_lowercase());
+    match extension.as_deref() {
+      Some("tex" | "ltx" | "latex") => FileRole::Tex,
+      Some("bib") => FileRole::Bib,
+      Some("bbl") => FileRole::Bbl,
+      Some("sty" | "cls" | "bst" | "clo" | "def" | "fd" | "cfg") => FileRole::Style,
+      Some("eps" | "ps" | "pdf" | "png" | "jpg" | "jpeg" | "gif" | "svg" | "tif" | "tiff") => {
+        FileRole::Figure
+      },
+      _ => FileRole::Other,
+    }
+  }
+
+  /// Whether TeX reads the file, so that a paper does not compile without it.
+  pub fn is_tex_input(&self) -> bool {
+    matches!(self, FileRole::Tex | FileRole::Bib | FileRole::Bbl | FileRole::Style)
+  }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct MissingInput {
+  /// The file doing the `\input`
+  pub file: String,
+  pub target: String,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct Manifest {
+  pub main_file: Option<String>,
+  pub files: BTreeMap<FileRole, Vec<String>>,
+  pub missing_inputs: Vec<MissingInput>,
+  /// Files left out of the output under the strip policy
+  pub stripped: Vec<StrippedEntry>,
+  /// The re-encoding of a single-file submission to UTF-8, if any
+  pub encoding: Option<TextEncoding>,
+}
+
+/// What a TeX file's text says about it.
+struct TexFacts<'a> {
+  path: &'a str,
+  size: usize,
+  has_documentclass: bool,
+  has_begin_document: bool,
+  inputs: Vec<String>,
+}
+
+impl<'a> TexFacts<'a> {
+  fn read(path: &'a str, data: &[u8]) -> Self {
+    let text = String::from_utf8_lossy(data);
+    let text = COMMENT_REGEX.replace_all(&text, "$1");
+    TexFacts {
+      path,
+      size: data.len(),
+      has_documentclass: text.contains("\\documentclass") || text.contains("\\documentstyle"),
+      has_begin_document: text.contains("\\begin{document}"),
+      inputs: INPUT_REGEX
+        .captures_iter(&text)
+        .filter_map(|cap| cap.get(1).or_else(|| cap.get(2)))
+        .map(|target| target.as_str().trim().to_owned())
+        .filter(|target| !target.is_empty() && !target.contains('#'))
+        .collect(),
+    }
+  }
+
+  fn is_document(&self) -> bool { self.has_documentclass || self.has_begin_document }
+}
+
+/// A manifest for a submission that is a single TeX file.
+pub fn single_file_manifest(path: &str) -> Manifest {
+  let role = FileRole::of(path);
+  Manifest {
+    main_file: (role == FileRole::Tex).then(|| path.to_owned()),
+    files: BTreeMap::from([(role, vec![path.to_owned()])]),
+    missing_inputs: Vec::new(),
+    stripped: Vec::new(),
+    encoding: None,
+  }
+}
+
+/// Write the manifest of the output just installed, replacing the previous one whole, or
+/// remove the previous one if the new output has none.
+pub(crate) fn record_manifest(to_dir: &str, manifest: Option<&Manifest>) -> io::Result<()> {
+  let manifest_path = Path::new(to_dir).join(MANIFEST_FILENAME);
+  let Some(manifest) = manifest else {
+    return match fs::remove_file(&manifest_path) {
+      Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+      _ => Ok(()),
+    };
+  };
+  let tmp_path = Path::new(to_dir).join(format!(".{MANIFEST_FILENAME}.tmp"));
+  fs::write(&tmp_path, serde_json::to_vec_pretty(manifest)?)?;
+  fs::rename(&tmp_path, &manifest_path)
+}
+
+/// The manifest of an installed output, as far as its files tell: a single file that is not
+/// TeX has none, and what was stripped or re-encoded on the way in is not known anymore.
+pub(crate) fn output_manifest(entries: &[(String, Vec<u8>)], base_name: &str) -> Option<Manifest> {
+  let files = || entries.iter().map(|(path, data)| (path.as_str(), data.as_slice()));
+  if let [(path, _)] = entries {
+    return classify_members(files()).is_tex().then(|| single_file_manifest(path));
+  }
+  let directives = find_directives(files()).and_then(|found| found.ok());
+  Some(build_manifest(files(), base_name, directives.as_ref()))
+}
+
+/// Build the manifest of a submission from its (sanitized) entries.
+pub fn build_manifest<'a>(
+  entries: impl Iterator<Item = (&'a str, &'a [u8])>,
+  base_name: &str,
+  directives: Option<&SubmissionDirectives>,
+) -> Manifest {
+  let mut manifest = Manifest::default();
+  let mut paths = HashSet::new();
+  let mut tex_facts = Vec::new();
+  for (path, data) in entries {
+    paths.insert(path);
+    let role = match FileRole::of(path) {
+      FileRole::Tex => {
+        tex_facts.push(TexFacts::read(path, data));
+        FileRole::Tex
+      },
+      // old submissions often carry extensionless TeX files
+      FileRole::Other if Path::new(path).extension().is_none() => {
+        let facts = TexFacts::read(path, data);
+        if facts.is_document() || !facts.inputs.is_empty() {
+          tex_facts.push(facts);
+          FileRole::Tex
+        } else {
+          FileRole::Other
+        }
+      },
+      role => role,
+    };
+    manifest.files.entry(role).or_default().push(path.to_owned());
+  }
+  for files in manifest.files.values_mut() {
+    files.sort();
+  }
+
+  let mut input_targets = HashSet::new();
+  for facts in tex_facts.iter() {
+    for target in facts.inputs.iter() {
+      match resolve_input(target, &paths) {
+        Some(resolved) => {
+          input_targets.insert(resolved);
+        },
+        None => manifest.missing_inputs.push(MissingInput {
+          file: facts.path.to_owned(),
+          target: target.clone(),
+        }),
+      }
+    }
+  }
+
+  let readme_main = directives
+    .and_then(|directives| directives.toplevel.first())
+    .filter(|toplevel| paths.contains(toplevel.as_str()));
+  manifest.main_file = match readme_main {
+    Some(toplevel) => Some(toplevel.clone()),
+    None => detect_main(&tex_facts, &input_targets, base_name),
+  };
+  manifest
+}
+
+/// Resolve an `\input` target the way TeX would, relative to the submission root,
+/// trying the `.tex` extension as well.
+fn resolve_input(target: &str, paths: &HashSet<&str>) -> Option<String> {
+  let target = normalize_entry_path(target)?;
+  [target.clone(), format!("{target}.tex")]
+    .into_iter()
+    .find(|candidate| paths.contains(candidate.as_str()))
+}
+
+fn detect_main(
+  tex_facts: &[TexFacts],
+  input_targets: &HashSet<String>,
+  base_name: &str,
+) -> Option<String> {
+  let mut candidates: Vec<&TexFacts> = tex_facts
+    .iter()
+    .filter(|facts| facts.is_document() && !input_targets.contains(facts.path))
+    .collect();
+  if candidates.is_empty() {
+    // no preamble anywhere, only a lone TeX file is a safe guess
+    return match tex_facts {
+      [only] => Some(only.path.to_owned()),
+      _ => None,
+    };
+  }
+  candidates.sort_by_key(|facts| {
+    let stem = Path::new(facts.path)
+      .file_stem()
+      .and_then(|stem| stem.to_str())
+      .unwrap_or_default();
+    let conventional = CONVENTIONAL_MAIN_NAMES.contains(&stem) || stem == base_name;
+    (
+      !(facts.has_documentclass && facts.has_begin_document),
+      !conventional,
+      facts.path.contains('/'),
+      std::cmp::Reverse(facts.size),
+      facts.path,
+    )
+  });
+  candidates.first().map(|facts| facts.path.to_owned())
+}
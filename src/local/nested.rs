@@ -0,0 +1,168 @@
+//! Expansion of archives nested inside a submission.
+//!
+//! Submitters often upload a tar holding further `.tar.gz`, `.zip` or `.gz` files, which arXiv
+//! leaves packed, so that LaTeXML cannot find the files they `\input`. When asked to, these are
+//! unpacked into the directory they sit in, as if the submitter had extracted them there. The
+//! archives themselves are kept too, as sources may refer to them by name, e.g. through
+//! `\DeclareGraphicsRule{.eps.gz}`.
+use std::collections::HashSet;
+use std::env;
+
+use super::archive::{backend, ReadMode};
+use super::limits::{LimitExceeded, LimitTracker};
+use super::sanitize::{normalize_entry_path, SanitizedEntry};
+use super::{read_sanitized_entries, ReadFailure, RepackageReport, SourceEntry};
+
+/// Levels of nested archives to unpack; unset or 0 leaves them packed.
+pub const EXPAND_NESTED_ENV: &str = "AR5IV_EXPAND_NESTED";
+
+/// Archive suffixes worth a look, longest first.
+const ARCHIVE_SUFFIXES: [&str; 8] =
+  [".tar.gz", ".tar.bz2", ".tar.xz", ".tgz", ".tar", ".zip", ".gz", ".bz2"];
+/// Suffixes of compressed single files, unpacked to the name without the suffix.
+const COMPRESSED_SUFFIXES: [&str; 2] = [".gz", ".bz2"];
+
+/// The expansion depth configured via `AR5IV_EXPAND_NESTED`, 0 if unset.
+pub fn depth_from_env() -> usize {
+  env::var(EXPAND_NESTED_ENV)
+    .ok()
+    .and_then(|depth| depth.parse().ok())
+    .unwrap_or(0)
+}
+
+fn archive_suffix(path: &str) -> Option<&'static str> {
+  let lower = path.to_ascii_lowercase();
+  ARCHIVE_SUFFIXES.into_iter().find(|suffix| lower.ends_with(suffix))
+}
+
+/// Add the contents of nested archives among `entries` next to them, down to `depth` levels.
+/// Inner entries are sanitized like outer ones and count against the same `limits`, but not
+/// towards the report's entries and bytes, which are the e-print's own;
+/// where an inner path collides with an existing entry, the existing one is kept.
+/// Anything that does not open as an archive is kept as it was.
+pub(super) fn expand_nested_archives(
+  entries: Vec<SourceEntry>,
+  depth: usize,
+  report: &mut RepackageReport,
+  limits: &mut LimitTracker,
+) -> Result<Vec<SourceEntry>, LimitExceeded> {
+  if depth == 0 {
+    return Ok(entries);
+  }
+  let mut taken: HashSet<String> = entries.iter().map(|entry| entry.path.clone()).collect();
+  let mut expanded = Vec::with_capacity(entries.len());
+  for mut entry in entries {
+    let Some(suffix) = archive_suffix(&entry.path) else {
+      expanded.push(entry);
+      continue;
+    };
+    let Some(inner) = read_nested(&mut entry, suffix, report, limits)? else {
+      expanded.push(entry);
+      continue;
+    };
+    let inner = expand_nested_archives(inner, depth - 1, report, limits)?;
+    report.expanded_archives.push(entry.path.clone());
+    let outer_path = entry.path.clone();
+    expanded.push(entry);
+    for inner_entry in inner {
+      if taken.insert(inner_entry.path.clone()) {
+        expanded.push(inner_entry);
+      } else {
+        report.sanitized_entries.push(SanitizedEntry {
+          original: format!("{outer_path}:{}", inner_entry.path),
+          sanitized: None,
+          reason: format!("duplicate of {:?}", inner_entry.path),
+        });
+      }
+    }
+  }
+  Ok(expanded)
+}
+
+/// The entries of a nested archive, with paths placed in the archive's own directory,
+/// or `None` if it is not an archive after all.
+fn read_nested(
+  entry: &mut SourceEntry,
+  suffix: &str,
+  report: &mut RepackageReport,
+  limits: &mut LimitTracker,
+) -> Result<Option<Vec<SourceEntry>>, LimitExceeded> {
+  let outer_path = entry.path.clone();
+  let directory = outer_path.rsplit_once('/').map(|(dir, _)| dir).unwrap_or("");
+  let mut inner = match backend().open_memory(&mut entry.data, ReadMode::Archive) {
+    Ok(mut reader) => {
+      let sanitized_before = report.sanitized_entries.len();
+      let counted = (report.entry_count, report.uncompressed_bytes);
+      let inner = read_sanitized_entries(reader.as_mut(), report, limits);
+      // the archive's bytes were counted as its own entry already
+      (report.entry_count, report.uncompressed_bytes) = counted;
+      let inner = match inner {
+        Ok(inner) => inner,
+        Err(ReadFailure::Limit(limit)) => return Err(limit),
+        // a truncated or corrupt archive is kept as it is, not expanded
+        Err(ReadFailure::Unreadable(_)) => {
+          report.sanitized_entries.truncate(sanitized_before);
+          return Ok(None);
+        },
+      };
+      // say which archive the sanitized inner entries came from
+      for sanitized in report.sanitized_entries[sanitized_before..].iter_mut() {
+        sanitized.original = format!("{outer_path}:{}", sanitized.original);
+      }
+      inner
+    },
+    Err(_) => Vec::new(),
+  };
+  if inner.is_empty() && COMPRESSED_SUFFIXES.contains(&suffix) {
+    // a lone compressed file, not a tar
+    match read_compressed_file(&mut entry.data, limits)? {
+      // the raw format passes uncompressed data through as is
+      Some(data) if data != entry.data => {
+        let path = outer_path[..outer_path.len() - suffix.len()]
+          .rsplit('/')
+          .next()
+          .unwrap_or_default()
+          .to_owned();
+        inner.push(SourceEntry { path, data });
+      },
+      _ => return Ok(None),
+    }
+  }
+  if inner.is_empty() {
+    return Ok(None);
+  }
+  Ok(Some(
+    inner
+      .into_iter()
+      .filter_map(|SourceEntry { path, data }| {
+        normalize_entry_path(&format!("{directory}/{path}")).map(|path| SourceEntry { path, data })
+      })
+      .collect(),
+  ))
+}
+
+fn read_compressed_file(
+  memory: &mut [u8],
+  limits: &mut LimitTracker,
+) -> Result<Option<Vec<u8>>, LimitExceeded> {
+  let Ok(mut reader) = backend().open_memory(memory, ReadMode::Raw) else {
+    return Ok(None);
+  };
+  if !matches!(reader.next_entry(), Ok(Some(_))) {
+    return Ok(None);
+  }
+  limits.start_entry()?;
+  let mut data = Vec::new();
+  loop {
+    match reader.read_chunk() {
+      Ok(Some(chunk)) => {
+        limits.add_bytes(chunk.len())?;
+        data.extend(chunk);
+      },
+      Ok(None) => break,
+      // a truncated or corrupt file is kept as it is, not expanded
+      Err(_) => return Ok(None),
+    }
+  }
+  Ok((!data.is_empty()).then_some(data))
+}
@@ -0,0 +1,244 @@
+//! Output formats for repackaged sources: zip (the default), tar+zstd, tar+gzip, or a plain
+//! extracted directory, each written through an `OutputSink`.
+use std::env;
+use std::fs::{self, File};
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+
+use super::archive::{backend, ArchiveWriter, ReadMode, WriteFormat};
+use super::fingerprint::content_hash;
+use super::sanitize::normalize_entry_path;
+use super::{io_error_at, RepackageError};
+
+/// `zip` (default), `tar.zst`, `tar.gz` or `dir`
+pub const OUTPUT_FORMAT_ENV: &str = "AR5IV_OUTPUT_FORMAT";
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OutputFormat {
+  #[default]
+  Zip,
+  TarZstd,
+  TarGzip,
+  /// The sources extracted into a `{id}/` directory
+  Directory,
+}
+
+impl OutputFormat {
+  pub const ALL: [OutputFormat; 4] = [
+    OutputFormat::Zip,
+    OutputFormat::TarZstd,
+    OutputFormat::TarGzip,
+    OutputFormat::Directory,
+  ];
+
+  pub fn name(&self) -> &'static str {
+    match self {
+      OutputFormat::Zip => "zip",
+      OutputFormat::TarZstd => "tar.zst",
+      OutputFormat::TarGzip => "tar.gz",
+      OutputFormat::Directory => "dir",
+    }
+  }
+
+  pub fn from_name(name: &str) -> Option<Self> {
+    OutputFormat::ALL
+      .into_iter()
+      .find(|format| format.name() == name)
+  }
+
+  /// The format configured via `AR5IV_OUTPUT_FORMAT`, zip if unset or unknown.
+  pub fn from_env() -> Self {
+    env::var(OUTPUT_FORMAT_ENV)
+      .ok()
+      .and_then(|name| OutputFormat::from_name(&name))
+      .unwrap_or_default()
+  }
+
+  /// The output's file (or directory) name, e.g. `{base_name}.zip`
+  pub fn file_name(&self, base_name: &str) -> String {
+    match self {
+      OutputFormat::Directory => base_name.to_owned(),
+      other => format!("{base_name}.{}", other.name()),
+    }
+  }
+
+  pub fn output_path(&self, to_dir: &str, base_name: &str) -> String {
+    format!("{to_dir}/{}", self.file_name(base_name))
+  }
+
+  /// Open a sink writing this format to `path`.
+  pub fn create(&self, path: &str) -> Result<Box<dyn OutputSink>, RepackageError> {
+    let format = match self {
+      OutputFormat::Directory => {
+        fs::create_dir_all(path).map_err(io_error_at(path))?;
+        return Ok(Box::new(DirectorySink {
+          root: PathBuf::from(path),
+        }));
+      },
+      OutputFormat::Zip => WriteFormat::Zip,
+      OutputFormat::TarZstd => WriteFormat::TarZstd,
+      OutputFormat::TarGzip => WriteFormat::TarGzip,
+    };
+    let writer = backend()
+      .create(Path::new(path), format)
+      .map_err(|e| RepackageError::archive(path, e))?;
+    Ok(Box::new(ArchiveSink { writer }))
+  }
+}
+
+/// Where repackaged entries are written to. Entries arrive with sanitized relative paths.
+pub trait OutputSink {
+  fn write_entry(&mut self, path: &str, data: Vec<u8>) -> Result<(), RepackageError>;
+}
+
+/// An archive written by the archive backend, closed when dropped.
+struct ArchiveSink {
+  writer: Box<dyn ArchiveWriter>,
+}
+
+impl OutputSink for ArchiveSink {
+  fn write_entry(&mut self, path: &str, data: Vec<u8>) -> Result<(), RepackageError> {
+    self
+      .writer
+      .write_entry(path, data)
+      .map_err(|e| RepackageError::archive(path, e))
+  }
+}
+
+struct DirectorySink {
+  root: PathBuf,
+}
+
+impl OutputSink for DirectorySink {
+  fn write_entry(&mut self, path: &str, data: Vec<u8>) -> Result<(), RepackageError> {
+    // paths are sanitized already, but a directory is less forgiving than an archive entry
+    let relative = normalize_entry_path(path)
+      .ok_or_else(|| RepackageError::archive(path, "entry escapes the output directory"))?;
+    let target = self.root.join(relative);
+    if let Some(parent) = target.parent() {
+      fs::create_dir_all(parent).map_err(io_error_at(&parent.display().to_string()))?;
+    }
+    let target_str = target.display().to_string();
+    let mut file = File::create(&target).map_err(io_error_at(&target_str))?;
+    file
+      .write_all(&data)
+      .and_then(|_| file.sync_all())
+      .map_err(io_error_at(&target_str))
+  }
+}
+
+/// All files of an output in any format, as (path, data), in the order they are stored.
+pub fn read_output_entries(path: &str) -> Result<Vec<(String, Vec<u8>)>, RepackageError> {
+  if Path::new(path).is_dir() {
+    let mut entries = Vec::new();
+    read_directory_entries(Path::new(path), "", &mut entries)?;
+    return Ok(entries);
+  }
+  let mut reader = backend()
+    .open_file(Path::new(path), ReadMode::Archive)
+    .map_err(|e| RepackageError::archive(path, format!("does not open: {e}")))?;
+  let unreadable = |e| RepackageError::archive(path, format!("does not read through: {e}"));
+  let mut entries = Vec::new();
+  while let Some(entry) = reader.next_entry().map_err(unreadable)? {
+    if entry.pathname.ends_with('/') {
+      continue; // directories, as in legacy zips, are implied by the paths of their files
+    }
+    let mut data = Vec::new();
+    while let Some(chunk) = reader.read_chunk().map_err(unreadable)? {
+      data.extend(chunk);
+    }
+    entries.push((entry.pathname, data));
+  }
+  Ok(entries)
+}
+
+fn read_directory_entries(
+  dir: &Path,
+  prefix: &str,
+  entries: &mut Vec<(String, Vec<u8>)>,
+) -> Result<(), RepackageError> {
+  let dir_str = dir.display().to_string();
+  let mut children: Vec<_> = fs::read_dir(dir)
+    .map_err(io_error_at(&dir_str))?
+    .flatten()
+    .collect();
+  children.sort_by_key(|child| child.file_name());
+  for child in children {
+    let name = child.file_name().to_string_lossy().into_owned();
+    let relative = if prefix.is_empty() {
+      name
+    } else {
+      format!("{prefix}/{name}")
+    };
+    let child_path = child.path();
+    if child_path.is_dir() {
+      read_directory_entries(&child_path, &relative, entries)?;
+    } else {
+      let data = fs::read(&child_path).map_err(io_error_at(&dir_str))?;
+      entries.push((relative, data));
+    }
+  }
+  Ok(())
+}
+
+/// Convert the output of one paper from format `from` to format `to`, checking that the
+/// content fingerprint survives, and remove the original. Returns the new output's path,
+/// `None` if the paper has no output in format `from`.
+pub fn convert_paper(
+  to_dir: &str,
+  base_name: &str,
+  from: OutputFormat,
+  to: OutputFormat,
+) -> Result<Option<String>, RepackageError> {
+  let source_path = from.output_path(to_dir, base_name);
+  if from == to || !Path::new(&source_path).exists() {
+    return Ok(None);
+  }
+  let target_path = to.output_path(to_dir, base_name);
+  let tmp_path = format!("{to_dir}/.{}.tmp", to.file_name(base_name));
+  let converted = (|| {
+    let expected = content_hash(&source_path)?;
+    {
+      let mut sink = to.create(&tmp_path)?;
+      for (path, data) in read_output_entries(&source_path)? {
+        sink.write_entry(&path, data)?;
+      }
+    } // dropping the sink closes the archive
+    let written = content_hash(&tmp_path)?;
+    if written != expected {
+      return Err(RepackageError::archive(
+        &tmp_path,
+        format!("converted output differs, {written:?} instead of {expected:?}"),
+      ));
+    }
+    install(&tmp_path, &target_path)?;
+    remove_output(&source_path);
+    Ok(Some(target_path))
+  })();
+  if converted.is_err() {
+    remove_output(&tmp_path);
+  }
+  converted
+}
+
+/// Move a freshly written output at `tmp_path` into place at `path`, replacing any previous one.
+pub(crate) fn install(tmp_path: &str, path: &str) -> Result<(), RepackageError> {
+  if Path::new(path).is_dir() {
+    // a directory can not be renamed over a non-empty one, so move the old one aside first
+    let old_path = format!("{path}.old");
+    fs::rename(path, &old_path).map_err(io_error_at(path))?;
+    fs::rename(tmp_path, path).map_err(io_error_at(path))?;
+    fs::remove_dir_all(&old_path).map_err(io_error_at(&old_path))
+  } else {
+    fs::rename(tmp_path, path).map_err(io_error_at(path))
+  }
+}
+
+/// Remove a (partially) written output, file or directory.
+pub(crate) fn remove_output(path: &str) {
+  let _ = if Path::new(path).is_dir() {
+    fs::remove_dir_all(path)
+  } else {
+    fs::remove_file(path)
+  };
+}
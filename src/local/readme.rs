@@ -0,0 +1,132 @@
+//! arXiv's `00README` processing directives.
+//!
+//! Submitters name the top-level TeX file, files to ignore, and the TeX engine either in the
+//! legacy line-based `00README.XXX`, or in `00README.json`. Both are normalized into
+//! `SubmissionDirectives`, which is written into the zip as `ar5iv-directives.json`,
+//! so that conversion knows where to start and with what.
+use std::env;
+
+use serde::{Deserialize, Serialize};
+
+use super::sanitize::normalize_entry_path;
+
+pub const README_JSON: &str = "00README.json";
+pub const README_XXX: &str = "00README.XXX";
+/// The zip entry holding the normalized directives
+pub const DIRECTIVES_ENTRY: &str = "ar5iv-directives.json";
+/// `1` or `true` to leave out the files a `00README` marks as `ignore`
+pub const DROP_IGNORED_ENV: &str = "AR5IV_DROP_IGNORED";
+
+/// Whether ignored files are dropped, as configured via `AR5IV_DROP_IGNORED`; off if unset.
+pub fn drop_ignored_from_env() -> bool {
+  env::var(DROP_IGNORED_ENV).is_ok_and(|drop| drop == "1" || drop.eq_ignore_ascii_case("true"))
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct SubmissionDirectives {
+  /// Which of the readme files these came from
+  pub source: String,
+  /// Top-level TeX files, in processing order
+  pub toplevel: Vec<String>,
+  pub ignore: Vec<String>,
+  pub include: Vec<String>,
+  /// Files to process after the top-level files
+  pub append: Vec<String>,
+  /// The TeX engine, e.g. `pdflatex`, `xelatex` or `lualatex`
+  pub compiler: Option<String>,
+  pub nohyperref: bool,
+  pub nostamp: bool,
+}
+
+#[derive(Debug, Deserialize)]
+struct ReadmeJson {
+  #[serde(default)]
+  process: Option<ReadmeProcess>,
+  #[serde(default)]
+  sources: Vec<ReadmeSource>,
+  #[serde(default)]
+  nohyperref: bool,
+  #[serde(default = "default_stamp")]
+  stamp: bool,
+}
+
+#[derive(Debug, Deserialize)]
+struct ReadmeProcess {
+  compiler: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ReadmeSource {
+  filename: String,
+  usage: Option<String>,
+}
+
+fn default_stamp() -> bool { true }
+
+impl SubmissionDirectives {
+  fn add_usage(&mut self, filename: &str, usage: &str) {
+    let Some(path) = normalize_entry_path(filename) else {
+      return;
+    };
+    match usage {
+      "toplevel" | "toplevelfile" => self.toplevel.push(path),
+      "ignore" => self.ignore.push(path),
+      "include" => self.include.push(path),
+      "append" => self.append.push(path),
+      _ => {},
+    }
+  }
+
+  pub fn is_ignored(&self, path: &str) -> bool { self.ignore.iter().any(|ignored| ignored == path) }
+}
+
+/// Parse a `00README.json`.
+pub fn parse_readme_json(text: &str) -> Result<SubmissionDirectives, serde_json::Error> {
+  let readme: ReadmeJson = serde_json::from_str(text)?;
+  let mut directives = SubmissionDirectives {
+    source: README_JSON.to_owned(),
+    compiler: readme.process.and_then(|process| process.compiler),
+    nohyperref: readme.nohyperref,
+    nostamp: !readme.stamp,
+    ..SubmissionDirectives::default()
+  };
+  for source in readme.sources.iter() {
+    if let Some(usage) = source.usage.as_deref() {
+      directives.add_usage(&source.filename, usage);
+    }
+  }
+  Ok(directives)
+}
+
+/// Parse a legacy `00README.XXX`: lines of `{file} {directive}`, or a lone global directive.
+/// Unknown lines are skipped, as arXiv's own processing does.
+pub fn parse_readme_xxx(text: &str) -> SubmissionDirectives {
+  let mut directives = SubmissionDirectives {
+    source: README_XXX.to_owned(),
+    ..SubmissionDirectives::default()
+  };
+  for line in text.lines().map(str::trim).filter(|line| !line.starts_with('#')) {
+    match line.split_whitespace().collect::<Vec<_>>()[..] {
+      ["nohypertex"] | ["nohyperref"] => directives.nohyperref = true,
+      ["nostamp"] => directives.nostamp = true,
+      [filename, usage] => directives.add_usage(filename, usage),
+      _ => {},
+    }
+  }
+  directives
+}
+
+/// The directives of a submission, from its top-level readme files, `00README.json` first.
+pub fn find_directives<'a>(
+  entries: impl Iterator<Item = (&'a str, &'a [u8])>,
+) -> Option<Result<SubmissionDirectives, serde_json::Error>> {
+  let mut xxx = None;
+  for (path, data) in entries {
+    if path == README_JSON {
+      return Some(parse_readme_json(&String::from_utf8_lossy(data)));
+    } else if path == README_XXX {
+      xxx = Some(parse_readme_xxx(&String::from_utf8_lossy(data)));
+    }
+  }
+  xxx.map(Ok)
+}
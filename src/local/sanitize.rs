@@ -0,0 +1,98 @@
+//! Entry path sanitization for submitter-provided archives.
+//!
+//! arXiv passes submitter tarballs through as uploaded, so entries may carry absolute paths,
+//! `..` components or links pointing anywhere. The LaTeXML workers extract our zips, so only
+//! plain relative paths that stay inside the paper's directory are let through.
+use serde::Serialize;
+
+/// An entry that could not be copied as-is, and what was done about it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SanitizedEntry {
+  pub original: String,
+  /// The path the entry was written under, `None` if it was dropped
+  pub sanitized: Option<String>,
+  pub reason: String,
+}
+
+/// Normalize an entry path to a plain relative path, resolving `.` and `..` components.
+/// Returns `None` if nothing is left, or if the path escapes the archive root.
+pub fn normalize_entry_path(raw: &str) -> Option<String> {
+  if raw.contains('\0') {
+    return None;
+  }
+  let unified = raw.replace('\\', "/");
+  // drop a DOS drive prefix, as in `C:/...`
+  let unified = match unified.as_bytes() {
+    [drive, b':', ..] if drive.is_ascii_alphabetic() => &unified[2..],
+    _ => unified.as_str(),
+  };
+  let mut components: Vec<&str> = Vec::new();
+  for component in unified.split('/') {
+    match component {
+      "" | "." => {},
+      ".." => {
+        components.pop()?;
+      },
+      other => components.push(other),
+    }
+  }
+  if components.is_empty() {
+    None
+  } else {
+    Some(components.join("/"))
+  }
+}
+
+/// Resolve a link `target` as seen from the entry at `link_path` (already normalized),
+/// to a normalized path inside the archive.
+pub fn resolve_link_target(link_path: &str, target: &str) -> Option<String> {
+  if target.starts_with('/') || target.starts_with('\\') {
+    return None;
+  }
+  let link_dir = link_path.rsplit_once('/').map(|(dir, _)| dir).unwrap_or("");
+  normalize_entry_path(&format!("{link_dir}/{target}"))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn entry_paths_normalize_inside_the_paper_directory() {
+    let cases = [
+      ("main.tex", Some("main.tex")),
+      ("./figs/a.png", Some("figs/a.png")),
+      ("figs//./a.png", Some("figs/a.png")),
+      ("figs/../main.tex", Some("main.tex")),
+      ("/etc/x", Some("etc/x")),
+      ("C:\\x", Some("x")),
+      ("C:\\figs\\a.png", Some("figs/a.png")),
+      ("../x", None),
+      ("a/../../x", None),
+      ("..", None),
+      ("", None),
+      ("./", None),
+      ("ma\0in.tex", None),
+    ];
+    for (raw, expected) in cases {
+      assert_eq!(normalize_entry_path(raw).as_deref(), expected, "{raw:?}");
+    }
+  }
+
+  #[test]
+  fn link_targets_resolve_from_the_link() {
+    let cases = [
+      ("alias.tex", "main.tex", Some("main.tex")),
+      ("figs/a.png", "b.png", Some("figs/b.png")),
+      ("figs/a.png", "../main.tex", Some("main.tex")),
+      ("figs/a.png", "../../x", None),
+      ("a.tex", "../x", None),
+      ("a.tex", "/etc/passwd", None),
+      ("a.tex", "\\etc\\passwd", None),
+      ("a.tex", "b\0.tex", None),
+    ];
+    for (link, target, expected) in cases {
+      assert_eq!(resolve_link_target(link, target).as_deref(), expected, "{link:?} -> {target:?}");
+    }
+  }
+}
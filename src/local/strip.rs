@@ -0,0 +1,138 @@
+//! Stripping of files not needed for conversion: ancillary `anc/` directories, files matching
+//! exclude globs, and oversized assets such as videos and data sets.
+//!
+//! Stripped files are listed in the manifest, with the reason, and can be had back by
+//! re-fetching the paper under a different policy.
+use std::env;
+
+use glob::{MatchOptions, Pattern};
+use serde::Serialize;
+use tracing::warn;
+
+use super::manifest::FileRole;
+use super::readme::DIRECTIVES_ENTRY;
+use super::SourceEntry;
+
+/// Comma-separated globs of files to strip, e.g. `*.mp4,*.h5`
+pub const EXCLUDE_ENV: &str = "AR5IV_EXCLUDE";
+/// Comma-separated globs of files to keep regardless, e.g. `anc/*.tex`
+pub const INCLUDE_ENV: &str = "AR5IV_INCLUDE";
+/// Files larger than this many bytes are stripped, TeX inputs (sources, styles, bibliographies)
+/// excepted
+pub const MAX_FILE_BYTES_ENV: &str = "AR5IV_MAX_FILE_BYTES";
+/// `1` or `true` to strip the ancillary `anc/` directory
+pub const STRIP_ANC_ENV: &str = "AR5IV_STRIP_ANC";
+
+const ANCILLARY_DIR: &str = "anc/";
+/// `*` crosses directories, so that `*.mp4` strips videos anywhere
+const MATCH_OPTIONS: MatchOptions = MatchOptions {
+  case_sensitive: false,
+  require_literal_separator: false,
+  require_literal_leading_dot: false,
+};
+
+/// What to strip. The default strips nothing.
+#[derive(Debug, Clone, Default)]
+pub struct StripPolicy {
+  /// Kept even if excluded, in `anc/` or too large
+  pub include: Vec<Pattern>,
+  pub exclude: Vec<Pattern>,
+  pub max_file_bytes: Option<u64>,
+  pub strip_anc: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct StrippedEntry {
+  pub path: String,
+  pub bytes: u64,
+  pub reason: String,
+}
+
+fn patterns_from_env(name: &str) -> Vec<Pattern> {
+  let Ok(globs) = env::var(name) else {
+    return Vec::new();
+  };
+  globs
+    .split(',')
+    .map(str::trim)
+    .filter(|glob| !glob.is_empty())
+    .filter_map(|glob| match Pattern::new(glob) {
+      Ok(pattern) => Some(pattern),
+      Err(e) => {
+        warn!(env = name, glob, error = %e, "ignoring invalid glob");
+        None
+      },
+    })
+    .collect()
+}
+
+impl StripPolicy {
+  /// The policy configured via `AR5IV_EXCLUDE`, `AR5IV_INCLUDE`, `AR5IV_MAX_FILE_BYTES`
+  /// and `AR5IV_STRIP_ANC`.
+  pub fn from_env() -> Self {
+    StripPolicy {
+      include: patterns_from_env(INCLUDE_ENV),
+      exclude: patterns_from_env(EXCLUDE_ENV),
+      max_file_bytes: env::var(MAX_FILE_BYTES_ENV)
+        .ok()
+        .and_then(|bytes| bytes.parse().ok()),
+      strip_anc: env::var(STRIP_ANC_ENV)
+        .is_ok_and(|strip| strip == "1" || strip.eq_ignore_ascii_case("true")),
+    }
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.exclude.is_empty() && self.max_file_bytes.is_none() && !self.strip_anc
+  }
+
+  /// Why the file at `path` should be stripped, if it should.
+  pub fn strip_reason(&self, path: &str, bytes: u64) -> Option<String> {
+    if path == DIRECTIVES_ENTRY
+      || self
+        .include
+        .iter()
+        .any(|pattern| pattern.matches_with(path, MATCH_OPTIONS))
+    {
+      return None;
+    }
+    if self.strip_anc && path.starts_with(ANCILLARY_DIR) {
+      return Some(String::from("ancillary file"));
+    }
+    if let Some(pattern) = self
+      .exclude
+      .iter()
+      .find(|pattern| pattern.matches_with(path, MATCH_OPTIONS))
+    {
+      return Some(format!("matches {:?}", pattern.as_str()));
+    }
+    match self.max_file_bytes {
+      Some(max) if bytes > max && !FileRole::of(path).is_tex_input() => {
+        Some(format!("larger than {max} bytes"))
+      },
+      _ => None,
+    }
+  }
+
+  /// Drop the entries this policy strips, returning what was dropped and why.
+  pub(super) fn apply(&self, entries: &mut Vec<SourceEntry>) -> Vec<StrippedEntry> {
+    let mut stripped = Vec::new();
+    if self.is_empty() {
+      return stripped;
+    }
+    entries.retain(|entry| {
+      let bytes = entry.data.len() as u64;
+      match self.strip_reason(&entry.path, bytes) {
+        Some(reason) => {
+          stripped.push(StrippedEntry {
+            path: entry.path.clone(),
+            bytes,
+            reason,
+          });
+          false
+        },
+        None => true,
+      }
+    });
+    stripped
+  }
+}
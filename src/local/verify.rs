@@ -0,0 +1,163 @@
+//! Integrity checks of the corpus: every paper directory should hold a readable output, with
+//! entries that read through (CRCs included, where the backend checks them), at least one TeX
+//! source, and the content fingerprint recorded when it was written.
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+use super::archive::{backend, ReadMode};
+use super::arxiv_id_of;
+use super::classify::{classify_members, SubmissionClass, SNIFF_LEN};
+use super::fingerprint::{combine, stored_hash};
+use super::output::{read_output_entries, OutputFormat};
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "problem", rename_all = "snake_case")]
+pub enum Problem {
+  /// No output in any format
+  NoOutput,
+  Unreadable { message: String },
+  Empty,
+  /// An entry that does not read through, e.g. truncated or failing its CRC
+  Corrupt { entry: String, message: String },
+  NoTex { class: SubmissionClass },
+  /// The content differs from the fingerprint recorded when the output was written
+  HashMismatch { expected: String, found: String },
+}
+
+impl Problem {
+  /// Whether downloading the paper again should fix it. PDF-only and other recognized
+  /// non-TeX submissions lack TeX sources by nature.
+  pub fn needs_refetch(&self) -> bool {
+    !matches!(self, Problem::NoTex { class } if *class != SubmissionClass::Unknown)
+  }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PaperCheck {
+  pub id: String,
+  pub dir: String,
+  /// The outputs found, in any format
+  pub outputs: Vec<String>,
+  pub problems: Vec<Problem>,
+}
+
+impl PaperCheck {
+  pub fn is_ok(&self) -> bool { self.problems.is_empty() }
+
+  pub fn needs_refetch(&self) -> bool { self.problems.iter().any(Problem::needs_refetch) }
+}
+
+/// Check the outputs of the paper in `to_dir`, in whichever formats they exist.
+pub fn verify_paper(to_dir: &str, base_name: &str) -> PaperCheck {
+  let mut check = PaperCheck {
+    id: arxiv_id_of(base_name),
+    dir: to_dir.to_owned(),
+    outputs: Vec::new(),
+    problems: Vec::new(),
+  };
+  for format in OutputFormat::ALL {
+    let output_path = format.output_path(to_dir, base_name);
+    if Path::new(&output_path).exists() {
+      check
+        .problems
+        .extend(verify_output(&output_path, stored_hash(to_dir, base_name)));
+      check.outputs.push(output_path);
+    }
+  }
+  if check.outputs.is_empty() {
+    check.problems.push(Problem::NoOutput);
+  }
+  check
+}
+
+/// Read an output through, checking each entry, and compare its fingerprint to `expected_hash`.
+fn verify_output(output_path: &str, expected_hash: Option<String>) -> Vec<Problem> {
+  let mut problems = Vec::new();
+  // the start of each entry, enough to classify the submission
+  let mut heads: Vec<(String, Vec<u8>)> = Vec::new();
+  let mut entry_digests = BTreeMap::new();
+  if Path::new(output_path).is_dir() {
+    match read_output_entries(output_path) {
+      Ok(entries) => {
+        for (path, data) in entries {
+          entry_digests.insert(path.clone(), Sha256::digest(&data));
+          heads.push((path, data[..data.len().min(SNIFF_LEN)].to_vec()));
+        }
+      },
+      Err(e) => {
+        return vec![Problem::Unreadable {
+          message: e.to_string(),
+        }]
+      },
+    }
+  } else {
+    let mut reader = match backend().open_file(Path::new(output_path), ReadMode::Archive) {
+      Ok(reader) => reader,
+      Err(e) => {
+        return vec![Problem::Unreadable {
+          message: e.to_string(),
+        }]
+      },
+    };
+    loop {
+      let entry = match reader.next_entry() {
+        Ok(Some(entry)) => entry,
+        Ok(None) => break,
+        Err(e) => {
+          problems.push(Problem::Unreadable {
+            message: e.to_string(),
+          });
+          break;
+        },
+      };
+      if entry.pathname.ends_with('/') {
+        continue; // directories, as `content_hash` has it
+      }
+      let mut hasher = Sha256::new();
+      let mut head = Vec::new();
+      loop {
+        match reader.read_chunk() {
+          Ok(Some(chunk)) => {
+            hasher.update(&chunk);
+            if head.len() < SNIFF_LEN {
+              head.extend_from_slice(&chunk[..chunk.len().min(SNIFF_LEN - head.len())]);
+            }
+          },
+          Ok(None) => break,
+          Err(e) => {
+            problems.push(Problem::Corrupt {
+              entry: entry.pathname.clone(),
+              message: e.to_string(),
+            });
+            break;
+          },
+        }
+      }
+      entry_digests.insert(entry.pathname.clone(), hasher.finalize());
+      heads.push((entry.pathname, head));
+    }
+  }
+
+  if heads.is_empty() {
+    problems.push(Problem::Empty);
+    return problems;
+  }
+  let class = classify_members(
+    heads
+      .iter()
+      .map(|(path, head)| (path.as_str(), head.as_slice())),
+  );
+  if !class.is_tex() {
+    problems.push(Problem::NoTex { class });
+  }
+  if let Some(expected) = expected_hash {
+    let found = combine(&entry_digests);
+    if found != expected {
+      problems.push(Problem::HashMismatch { expected, found });
+    }
+  }
+  problems
+}
@@ -0,0 +1,242 @@
+//! Retention of a paper's earlier source versions, when a new download replaces `{id}.zip`.
+//!
+//! The current sources always stay at the canonical `{id}.zip`. Replaced ones are archived as
+//! `{id}v{N}.zip` next to it, or as `versions/{id}v{N}.zip`, depending on the layout.
+//! The version of the canonical zip is remembered in a `{id}.version` sidecar, when known.
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use lazy_static::lazy_static;
+use regex::Regex;
+
+use super::fingerprint::{content_hash, record_hash};
+use super::manifest::{output_manifest, record_manifest};
+use super::output::read_output_entries;
+use crate::error::{io_at, Ar5ivError, Result};
+
+pub const VERSIONS_SUBDIR: &str = "versions";
+/// Number of earlier versions to keep; unset or 0 disables retention.
+pub const KEEP_VERSIONS_ENV: &str = "AR5IV_KEEP_VERSIONS";
+/// `suffix` (default) or `subdir`
+pub const VERSIONS_LAYOUT_ENV: &str = "AR5IV_VERSIONS_LAYOUT";
+
+lazy_static! {
+  /// `v{N}` at the end of a file name, before any extensions
+  static ref FILENAME_VERSION_REGEX: Regex = Regex::new(r"v(\d+)(?:\.[A-Za-z0-9.]*)?$").unwrap();
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RetentionLayout {
+  /// `{id}v{N}.zip` next to `{id}.zip`
+  Suffix,
+  /// `versions/{id}v{N}.zip`
+  Subdirectory,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetentionPolicy {
+  pub layout: RetentionLayout,
+  /// How many archived versions to keep, the oldest are removed first.
+  pub keep: usize,
+}
+
+impl RetentionPolicy {
+  /// The policy configured via `AR5IV_KEEP_VERSIONS` and `AR5IV_VERSIONS_LAYOUT`, if any.
+  pub fn from_env() -> Option<Self> {
+    let keep = env::var(KEEP_VERSIONS_ENV).ok()?.parse().ok()?;
+    if keep == 0 {
+      return None;
+    }
+    let layout = match env::var(VERSIONS_LAYOUT_ENV).as_deref() {
+      Ok("subdir") => RetentionLayout::Subdirectory,
+      _ => RetentionLayout::Suffix,
+    };
+    Some(RetentionPolicy { layout, keep })
+  }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArchivedVersion {
+  pub version: usize,
+  pub path: PathBuf,
+}
+
+fn version_sidecar(to_dir: &str, base_name: &str) -> PathBuf {
+  Path::new(to_dir).join(format!("{base_name}.version"))
+}
+
+fn archived_path(to_dir: &str, base_name: &str, version: usize, layout: RetentionLayout) -> PathBuf {
+  let file_name = format!("{base_name}v{version}.zip");
+  match layout {
+    RetentionLayout::Suffix => Path::new(to_dir).join(file_name),
+    RetentionLayout::Subdirectory => Path::new(to_dir).join(VERSIONS_SUBDIR).join(file_name),
+  }
+}
+
+/// The arXiv version in the name of a file arXiv served, e.g. 2 for `2101.00001v2.tar.gz`
+/// or `hep-th9901001v2.pdf`.
+pub fn version_from_filename(filename: &str) -> Option<usize> {
+  FILENAME_VERSION_REGEX
+    .captures(filename)?
+    .get(1)?
+    .as_str()
+    .parse()
+    .ok()
+}
+
+/// The latest versions found by `check_arxiv_versions`, from its `{id},{version}` lines.
+pub fn checked_versions(checked_ids_filepath: &str) -> Result<HashMap<String, usize>> {
+  let checked = match fs::read_to_string(checked_ids_filepath) {
+    Ok(checked) => checked,
+    Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(HashMap::new()),
+    Err(e) => return Err(io_at(checked_ids_filepath)(e)),
+  };
+  Ok(
+    checked
+      .lines()
+      .filter_map(|line| {
+        let (id, version) = line.split_once(',')?;
+        Some((id.to_owned(), version.trim().parse().ok()?))
+      })
+      .collect(),
+  )
+}
+
+/// The arXiv version of the canonical `{id}.zip`, if it was recorded.
+pub fn current_version(to_dir: &str, base_name: &str) -> Option<usize> {
+  fs::read_to_string(version_sidecar(to_dir, base_name))
+    .ok()?
+    .trim()
+    .parse()
+    .ok()
+}
+
+/// Remember `version` as the version of the canonical zip, or forget it if unknown.
+pub(crate) fn record_version(to_dir: &str, base_name: &str, version: Option<usize>) -> io::Result<()> {
+  let sidecar = version_sidecar(to_dir, base_name);
+  match version {
+    Some(version) => fs::write(sidecar, format!("{version}\n")),
+    None => match fs::remove_file(sidecar) {
+      Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+      _ => Ok(()),
+    },
+  }
+}
+
+/// All archived versions of a paper, in either layout, oldest first.
+pub fn list_versions(to_dir: &str, base_name: &str) -> Result<Vec<ArchivedVersion>> {
+  let mut versions = Vec::new();
+  let prefix = format!("{base_name}v");
+  for dir in [
+    PathBuf::from(to_dir),
+    Path::new(to_dir).join(VERSIONS_SUBDIR),
+  ] {
+    let entries = match fs::read_dir(&dir) {
+      Ok(entries) => entries,
+      Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+      Err(e) => return Err(io_at(&dir)(e)),
+    };
+    for entry in entries.flatten() {
+      let file_name = entry.file_name();
+      let version = file_name
+        .to_str()
+        .and_then(|name| name.strip_prefix(&prefix))
+        .and_then(|name| name.strip_suffix(".zip"))
+        .and_then(|number| number.parse().ok());
+      if let Some(version) = version {
+        versions.push(ArchivedVersion {
+          version,
+          path: entry.path(),
+        });
+      }
+    }
+  }
+  versions.sort_by_key(|archived| archived.version);
+  Ok(versions)
+}
+
+/// Link the canonical zip under its version number, before it gets replaced. It stays in place,
+/// so that a failed replacement leaves the paper as it was; see `discard_retained` for that case.
+/// `incoming` is the version about to become canonical, if known.
+pub(crate) fn retain_current(
+  to_dir: &str,
+  base_name: &str,
+  policy: RetentionPolicy,
+  incoming: Option<usize>,
+) -> Result<Option<ArchivedVersion>> {
+  let current = Path::new(to_dir).join(format!("{base_name}.zip"));
+  if !current.exists() {
+    return Ok(None);
+  }
+  let archived = list_versions(to_dir, base_name)?;
+  // Prefer the recorded version. Otherwise the one before the incoming, or, when neither is
+  // known, the next number after the archived ones, i.e. numbered by arrival.
+  let version = current_version(to_dir, base_name)
+    .or_else(|| incoming.map(|incoming| incoming.saturating_sub(1)).filter(|v| *v > 0))
+    .unwrap_or_else(|| archived.last().map(|last| last.version + 1).unwrap_or(1));
+  let path = archived_path(to_dir, base_name, version, policy.layout);
+  if let Some(parent) = path.parent() {
+    fs::create_dir_all(parent).map_err(io_at(parent))?;
+  }
+  // an earlier download of the same version is superseded
+  match fs::remove_file(&path) {
+    Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(io_at(&path)(e)),
+    _ => {},
+  }
+  fs::hard_link(&current, &path)
+    .or_else(|_| fs::copy(&current, &path).map(|_| ()))
+    .map_err(io_at(&path))?;
+  Ok(Some(ArchivedVersion { version, path }))
+}
+
+/// Undo `retain_current`, when the canonical zip was not replaced after all.
+pub(crate) fn discard_retained(retained: &ArchivedVersion) -> Result<()> {
+  fs::remove_file(&retained.path).map_err(io_at(&retained.path))
+}
+
+/// Remove the oldest archived versions, down to `policy.keep`.
+pub(crate) fn prune_versions(to_dir: &str, base_name: &str, policy: RetentionPolicy) -> Result<()> {
+  let mut archived = list_versions(to_dir, base_name)?;
+  while archived.len() > policy.keep {
+    let oldest = archived.remove(0);
+    fs::remove_file(&oldest.path).map_err(io_at(&oldest.path))?;
+  }
+  Ok(())
+}
+
+/// Make archived `version` the canonical zip again. The replaced canonical zip is archived
+/// in turn (never pruned here), so a restore can itself be undone. The fingerprint and the
+/// manifest are rebuilt from the restored zip.
+pub fn restore_version(to_dir: &str, base_name: &str, version: usize) -> Result<ArchivedVersion> {
+  let source = list_versions(to_dir, base_name)?
+    .into_iter()
+    .find(|archived| archived.version == version)
+    .ok_or_else(|| Ar5ivError::State {
+      path: to_dir.to_owned(),
+      message: format!("no archived version {version} of {base_name}"),
+    })?;
+  let layout = if source.path.parent() == Some(Path::new(to_dir)) {
+    RetentionLayout::Suffix
+  } else {
+    RetentionLayout::Subdirectory
+  };
+  let tmp_path = Path::new(to_dir).join(format!(".{base_name}.zip.tmp"));
+  fs::copy(&source.path, &tmp_path).map_err(io_at(&tmp_path))?;
+  let unbounded = RetentionPolicy {
+    layout,
+    keep: usize::MAX,
+  };
+  retain_current(to_dir, base_name, unbounded, None)?;
+  let current = Path::new(to_dir).join(format!("{base_name}.zip"));
+  fs::rename(&tmp_path, &current).map_err(io_at(&current))?;
+  record_version(to_dir, base_name, Some(version)).map_err(io_at(to_dir))?;
+  let current = current.display().to_string();
+  let (_, hash) = content_hash(&current)?;
+  record_hash(to_dir, base_name, &hash).map_err(io_at(to_dir))?;
+  let manifest = output_manifest(&read_output_entries(&current)?, base_name);
+  record_manifest(to_dir, manifest.as_ref()).map_err(io_at(to_dir))?;
+  Ok(source)
+}
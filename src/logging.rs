@@ -0,0 +1,22 @@
+//! Log output setup shared by all binaries.
+//!
+//! Events go to stderr, filtered by `RUST_LOG` (default `info`). Set `AR5IV_LOG_FORMAT=json`
+//! for one JSON object per line, e.g. to grep and aggregate the events of a single paper.
+use std::env;
+
+use tracing_subscriber::EnvFilter;
+
+pub const LOG_FORMAT_ENV: &str = "AR5IV_LOG_FORMAT";
+
+/// Install the global subscriber. Call once, at the start of `main`.
+pub fn init() {
+  let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+  let builder = tracing_subscriber::fmt()
+    .with_env_filter(filter)
+    .with_writer(std::io::stderr);
+  if env::var(LOG_FORMAT_ENV).is_ok_and(|format| format.eq_ignore_ascii_case("json")) {
+    builder.json().with_current_span(true).init();
+  } else {
+    builder.init();
+  }
+}
@@ -0,0 +1,169 @@
+//! Prometheus metrics for update runs, exported via node_exporter's textfile collector.
+//!
+//! Counts are gathered process-wide while a command runs, and written out once at the end,
+//! as `ar5iv_{job}.prom` in the directory named by `AR5IV_METRICS_DIR` (default: current dir).
+use std::collections::BTreeMap;
+use std::env;
+use std::fmt::{self, Write as FmtWrite};
+use std::fs::{self, File};
+use std::io::prelude::*;
+use std::path::Path;
+use std::sync::Mutex;
+
+use chrono::Utc;
+use once_cell::sync::Lazy;
+
+use crate::error::{io_at, Result};
+
+pub const METRICS_DIR_ENV: &str = "AR5IV_METRICS_DIR";
+const LAST_SUCCESS_METRIC: &str = "ar5iv_last_success_timestamp_seconds";
+
+#[derive(Debug, Default)]
+struct Metrics {
+  ids_harvested: u64,
+  downloads: BTreeMap<&'static str, u64>,
+  http_responses: BTreeMap<(&'static str, u16), u64>,
+  bytes_downloaded: u64,
+  repackage_failures: u64,
+  corpus_papers: Option<u64>,
+}
+
+static METRICS: Lazy<Mutex<Metrics>> = Lazy::new(|| Mutex::new(Metrics::default()));
+
+fn with_metrics(record: impl FnOnce(&mut Metrics)) {
+  // metrics are best-effort, a poisoned lock from a panicked worker is still good to count with.
+  let mut metrics = METRICS.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+  record(&mut metrics);
+}
+
+/// Start counting afresh, for a process that runs more than once, e.g. `cron_update daemon`.
+pub fn reset() {
+  with_metrics(|m| *m = Metrics::default());
+}
+
+pub fn record_ids_harvested(count: usize) {
+  with_metrics(|m| m.ids_harvested += count as u64);
+}
+
+/// Count a finished download attempt, by `outcome` label, with the bytes it transferred.
+pub fn record_download(outcome: &'static str, bytes: usize) {
+  with_metrics(|m| {
+    *m.downloads.entry(outcome).or_insert(0) += 1;
+    m.bytes_downloaded += bytes as u64;
+  });
+}
+
+/// Count an HTTP response from an arXiv `endpoint`, e.g. "e-print", "abs" or "oai2".
+pub fn record_http_status(endpoint: &'static str, code: u16) {
+  with_metrics(|m| *m.http_responses.entry((endpoint, code)).or_insert(0) += 1);
+}
+
+pub fn record_repackage_failure() {
+  with_metrics(|m| m.repackage_failures += 1);
+}
+
+pub fn set_corpus_papers(count: usize) {
+  with_metrics(|m| m.corpus_papers = Some(count as u64));
+}
+
+/// Write the textfile for `job` (e.g. "fetch", "version_check", "daily").
+/// The last-success timestamp is only advanced if `succeeded`, otherwise carried over
+/// from the previous textfile. The file is replaced atomically, via a rename.
+pub fn write_textfile(job: &str, succeeded: bool) -> Result<()> {
+  let metrics_dir = env::var(METRICS_DIR_ENV).unwrap_or_else(|_| String::from("."));
+  let prom_path = Path::new(&metrics_dir).join(format!("ar5iv_{job}.prom"));
+  let last_success = if succeeded {
+    Some(Utc::now().timestamp())
+  } else {
+    previous_last_success(&prom_path)
+  };
+  let text = {
+    let metrics = METRICS.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    render_textfile(&metrics, job, last_success).expect("formatting into a String cannot fail")
+  };
+
+  // node_exporter may read at any time, so never let it see a partially written file.
+  let tmp_path = prom_path.with_extension("prom.tmp");
+  let mut tmp_file = File::create(&tmp_path).map_err(io_at(&tmp_path))?;
+  tmp_file
+    .write_all(text.as_bytes())
+    .and_then(|_| tmp_file.sync_all())
+    .map_err(io_at(&tmp_path))?;
+  fs::rename(&tmp_path, &prom_path).map_err(io_at(&prom_path))?;
+  Ok(())
+}
+
+fn render_textfile(
+  metrics: &Metrics,
+  job: &str,
+  last_success: Option<i64>,
+) -> Result<String, fmt::Error> {
+  let mut text = String::new();
+  let label = format!("job=\"{job}\"");
+  if let Some(timestamp) = last_success {
+    writeln!(text, "# HELP {LAST_SUCCESS_METRIC} Unix time of the last successful run.")?;
+    writeln!(text, "# TYPE {LAST_SUCCESS_METRIC} gauge")?;
+    writeln!(text, "{LAST_SUCCESS_METRIC}{{{label}}} {timestamp}")?;
+  }
+  writeln!(text, "# HELP ar5iv_ids_harvested Article ids listed via OAI in the last run.")?;
+  writeln!(text, "# TYPE ar5iv_ids_harvested gauge")?;
+  writeln!(text, "ar5iv_ids_harvested{{{label}}} {}", metrics.ids_harvested)?;
+  writeln!(text, "# HELP ar5iv_downloads E-print downloads in the last run, by outcome.")?;
+  writeln!(text, "# TYPE ar5iv_downloads gauge")?;
+  for (outcome, count) in metrics.downloads.iter() {
+    writeln!(text, "ar5iv_downloads{{{label},outcome=\"{outcome}\"}} {count}")?;
+  }
+  writeln!(
+    text,
+    "# HELP ar5iv_http_responses HTTP responses from arXiv in the last run, by endpoint and code."
+  )?;
+  writeln!(text, "# TYPE ar5iv_http_responses gauge")?;
+  for ((endpoint, code), count) in metrics.http_responses.iter() {
+    writeln!(
+      text,
+      "ar5iv_http_responses{{{label},endpoint=\"{endpoint}\",code=\"{code}\"}} {count}"
+    )?;
+  }
+  writeln!(text, "# HELP ar5iv_downloaded_bytes E-print bytes downloaded in the last run.")?;
+  writeln!(text, "# TYPE ar5iv_downloaded_bytes gauge")?;
+  writeln!(text, "ar5iv_downloaded_bytes{{{label}}} {}", metrics.bytes_downloaded)?;
+  writeln!(text, "# HELP ar5iv_repackage_failures Downloads not repackaged in the last run.")?;
+  writeln!(text, "# TYPE ar5iv_repackage_failures gauge")?;
+  writeln!(text, "ar5iv_repackage_failures{{{label}}} {}", metrics.repackage_failures)?;
+  if let Some(papers) = metrics.corpus_papers {
+    writeln!(text, "# HELP ar5iv_corpus_papers Paper directories in the local corpus.")?;
+    writeln!(text, "# TYPE ar5iv_corpus_papers gauge")?;
+    writeln!(text, "ar5iv_corpus_papers{{{label}}} {papers}")?;
+  }
+  Ok(text)
+}
+
+fn previous_last_success(prom_path: &Path) -> Option<i64> {
+  let previous = fs::read_to_string(prom_path).ok()?;
+  previous
+    .lines()
+    .find(|line| line.starts_with(LAST_SUCCESS_METRIC))
+    .and_then(|line| line.rsplit(' ').next())
+    .and_then(|timestamp| timestamp.parse().ok())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn rendered() -> String {
+    let metrics = METRICS.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    render_textfile(&metrics, "test", None).unwrap()
+  }
+
+  #[test]
+  fn reset_starts_a_new_run() {
+    reset();
+    record_ids_harvested(5);
+    record_ids_harvested(5);
+    assert!(rendered().contains("ar5iv_ids_harvested{job=\"test\"} 10\n"));
+    reset();
+    record_ids_harvested(5);
+    assert!(rendered().contains("ar5iv_ids_harvested{job=\"test\"} 5\n"));
+  }
+}
@@ -0,0 +1,169 @@
+//! Webhook notifications about the outcome of an update run.
+use std::env;
+use std::thread;
+use std::time::Duration;
+
+use serde::Serialize;
+use tracing::warn;
+
+use crate::error::{Ar5ivError, Result};
+use crate::report::RunReport;
+
+/// Environment variable holding the URL to POST run summaries to; unset disables notifications.
+pub const WEBHOOK_URL_ENV: &str = "AR5IV_WEBHOOK_URL";
+const WEBHOOK_TIMEOUT_SECS: u64 = 10;
+const WEBHOOK_ATTEMPTS: u64 = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RunStatus {
+  Success,
+  /// The run completed, but some papers could not be downloaded or processed.
+  PartialFailure,
+  /// The run was aborted.
+  Failure,
+}
+
+impl RunStatus {
+  pub fn of(report: &RunReport, completed: bool) -> Self {
+    if !completed {
+      RunStatus::Failure
+    } else if report.errors.is_empty()
+      && report
+        .fetch
+        .as_ref()
+        .is_none_or(|fetch| fetch.only_withdrawn_failed())
+    {
+      RunStatus::Success
+    } else {
+      RunStatus::PartialFailure
+    }
+  }
+}
+
+#[derive(Serialize)]
+struct Notification<'a> {
+  status: RunStatus,
+  report: &'a RunReport,
+}
+
+/// POST the run summary to the URL in `AR5IV_WEBHOOK_URL`, if set.
+/// Failures are reported and swallowed, a notification should never fail a run.
+pub fn notify_from_env(status: RunStatus, report: &RunReport) {
+  if let Ok(url) = env::var(WEBHOOK_URL_ENV) {
+    if let Err(e) = notify_webhook(&url, status, report) {
+      warn!(url, ?status, error = ?e, "webhook notification failed");
+    }
+  }
+}
+
+/// POST `{"status": ..., "report": {...}}` to `url`, retrying with a linear backoff.
+pub fn notify_webhook(url: &str, status: RunStatus, report: &RunReport) -> Result<()> {
+  let client = reqwest::blocking::Client::builder()
+    .user_agent("ar5iv (https://ar5iv.labs.arxiv.org)")
+    .timeout(Duration::from_secs(WEBHOOK_TIMEOUT_SECS))
+    .build()?;
+  let body = serde_json::to_string(&Notification { status, report })?;
+  let mut last_error = None;
+  for attempt in 1..=WEBHOOK_ATTEMPTS {
+    match client
+      .post(url)
+      .header(reqwest::header::CONTENT_TYPE, "application/json")
+      .body(body.clone())
+      .send()
+    {
+      Ok(resp) if resp.status().is_success() => return Ok(()),
+      Ok(resp) => {
+        last_error = Some(Ar5ivError::HttpStatus {
+          url: url.to_owned(),
+          status: resp.status().as_u16(),
+        })
+      },
+      Err(e) => last_error = Some(e.into()),
+    }
+    if attempt < WEBHOOK_ATTEMPTS {
+      thread::sleep(Duration::from_secs(attempt));
+    }
+  }
+  Err(last_error.expect("at least one attempt was made"))
+}
+
+#[cfg(test)]
+mod tests {
+  use std::io::{prelude::*, BufReader};
+  use std::net::TcpListener;
+  use std::thread::JoinHandle;
+
+  use super::*;
+
+  /// Answer one POST per status in `statuses`, on a local port. Returns the webhook URL and
+  /// a handle yielding the request bodies received.
+  fn serve(statuses: Vec<u16>) -> (String, JoinHandle<Vec<String>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/hook", listener.local_addr().unwrap());
+    let server = thread::spawn(move || {
+      let mut bodies = Vec::new();
+      for status in statuses {
+        let (stream, _) = listener.accept().unwrap();
+        let mut reader = BufReader::new(stream);
+        let mut content_length = 0;
+        let mut line = String::new();
+        while reader.read_line(&mut line).unwrap() > 0 && line != "\r\n" {
+          if let Some((name, value)) = line.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+              content_length = value.trim().parse().unwrap();
+            }
+          }
+          line.clear();
+        }
+        let mut body = vec![0; content_length];
+        reader.read_exact(&mut body).unwrap();
+        bodies.push(String::from_utf8(body).unwrap());
+        // a fresh connection per attempt, so that every one of them is accepted here
+        write!(
+          reader.get_mut(),
+          "HTTP/1.1 {status} Whatever\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+        )
+        .unwrap();
+      }
+      bodies
+    });
+    (url, server)
+  }
+
+  #[test]
+  fn posts_status_and_report() {
+    let (url, server) = serve(vec![200]);
+    let mut report = RunReport::new("2024-01-02");
+    report.harvested = 7;
+    notify_webhook(&url, RunStatus::PartialFailure, &report).unwrap();
+    let bodies = server.join().unwrap();
+    assert_eq!(bodies.len(), 1);
+    let body: serde_json::Value = serde_json::from_str(&bodies[0]).unwrap();
+    assert_eq!(body["status"], "partial_failure");
+    assert_eq!(body["report"]["date"], "2024-01-02");
+    assert_eq!(body["report"]["harvested"], 7);
+  }
+
+  #[test]
+  fn retries_until_accepted() {
+    let (url, server) = serve(vec![503, 200]);
+    let report = RunReport::new("2024-01-02");
+    notify_webhook(&url, RunStatus::Success, &report).unwrap();
+    let bodies = server.join().unwrap();
+    assert_eq!(bodies.len(), 2);
+    assert_eq!(bodies[0], bodies[1]);
+  }
+
+  #[test]
+  fn gives_up_after_the_last_attempt() {
+    let (url, server) = serve(vec![500; WEBHOOK_ATTEMPTS as usize]);
+    let report = RunReport::new("2024-01-02");
+    let result = notify_webhook(&url, RunStatus::Failure, &report);
+    assert!(matches!(
+      result,
+      Err(Ar5ivError::HttpStatus { status: 500, .. })
+    ));
+    assert_eq!(server.join().unwrap().len(), WEBHOOK_ATTEMPTS as usize);
+  }
+}
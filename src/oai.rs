@@ -1,67 +1,105 @@
-use std::error::Error;
 use std::thread;
 use std::time::Duration;
 use libxml::parser::Parser;
 
-pub fn fetch_article_list_since(yyyymmdd: &str) -> Result<Vec<String>, Box<dyn Error>> {
-  assert!(
-    yyyymmdd.split('-').count() == 3,
-    "expecting a date string in the format YYYY-MM-DD"
-  );
+use crate::error::{Ar5ivError, Result};
+use crate::metrics;
+
+const OAI_ID_PREFIX: &str = "oai:arXiv.org:";
+
+pub fn fetch_article_list_since(yyyymmdd: &str) -> Result<Vec<String>> {
+  if yyyymmdd.split('-').count() != 3 {
+    return Err(Ar5ivError::Parse(format!(
+      "expecting a date string in the format YYYY-MM-DD, got {yyyymmdd:?}"
+    )));
+  }
   let oai_arxiv_url = format!(
     "http://export.arxiv.org/oai2?verb=ListIdentifiers&metadataPrefix=oai_dc&from={yyyymmdd}");
   fetch_article_list_by_url(oai_arxiv_url)
 }
 
-pub fn fetch_article_list_by_url(url_owned: String) -> Result<Vec<String>, Box<dyn Error>> {
+pub fn fetch_article_list_by_url(url_owned: String) -> Result<Vec<String>> {
   let client = reqwest::blocking::Client::builder()
     .user_agent("ar5iv (https://ar5iv.labs.arxiv.org)")
     .build()?;
-  let mut ids = Vec::new();
   let url = url_owned.as_str();
+  let mut last_error = None;
   for _retries in 0..3 {
-    if let Ok(resp) = client.get(url).send() {
-      let status = resp.status();
-      if status != 200 {
-        if status == 503 {
-          thread::sleep(Duration::from_secs(10));
-        }
-      } else {
-        let payload = resp.text()?;
-        if !payload.is_empty() {
-          let parser = Parser::default();
-          if let Ok(doc) = parser.parse_string(payload) {
-            if let Some(root) = doc.get_root_readonly() {
-              for version_node in root
-                .findnodes("//*[local-name()='identifier']", &doc)
-                .unwrap_or_default()
-              {
-                let oai_id = version_node.get_content();
-                if !oai_id.is_empty() {
-                  ids.push(oai_id[14..].to_string());
-                }
-              }
-
-              // Check for a resumption token, in which case we recurse into the next set:
-              // <resumptionToken cursor=\"0\" completeListSize=\"34188\">6380191|10001</resumptionToken>
-              let resumption_nodes = root.findnodes("//*[local-name()='resumptionToken']", &doc).unwrap_or_default();
-              if let Some(resumption_node) = resumption_nodes.first() {
-                let resume_token = resumption_node.get_content();
-                if !resume_token.is_empty() {
-                  ids.extend(fetch_article_list_resume(&resume_token)?);
-                }
-              }
-            }
+    match client.get(url).send() {
+      Ok(resp) => {
+        let status = resp.status();
+        metrics::record_http_status("oai2", status.as_u16());
+        if status != 200 {
+          if status == 503 {
+            thread::sleep(Duration::from_secs(10));
           }
+          last_error = Some(Ar5ivError::HttpStatus {
+            url: url_owned.clone(),
+            status: status.as_u16(),
+          });
+        } else {
+          return parse_article_list(resp.text()?);
         }
-        break;
-      }
+      },
+      Err(e) => last_error = Some(e.into()),
+    }
+  }
+  Err(last_error.unwrap_or_else(|| Ar5ivError::Oai(format!("no response from {url}"))))
+}
+
+fn parse_article_list(payload: String) -> Result<Vec<String>> {
+  let mut ids = Vec::new();
+  if payload.is_empty() {
+    return Ok(ids);
+  }
+  let parser = Parser::default();
+  let doc = parser
+    .parse_string(payload)
+    .map_err(|e| Ar5ivError::Oai(format!("malformed response: {e:?}")))?;
+  let root = doc
+    .get_root_readonly()
+    .ok_or_else(|| Ar5ivError::Oai(String::from("response has no root element")))?;
+
+  // An empty date range is reported as an error, but is a perfectly normal day for us.
+  // <error code="noRecordsMatch">No records match</error>
+  if let Some(error_node) = root
+    .findnodes("//*[local-name()='error']", &doc)
+    .unwrap_or_default()
+    .first()
+  {
+    let code = error_node.get_attribute("code").unwrap_or_default();
+    if code == "noRecordsMatch" {
+      return Ok(ids);
+    }
+    return Err(Ar5ivError::Oai(format!("{code}: {}", error_node.get_content())));
+  }
+
+  for version_node in root
+    .findnodes("//*[local-name()='identifier']", &doc)
+    .unwrap_or_default()
+  {
+    let oai_id = version_node.get_content();
+    if !oai_id.is_empty() {
+      let id = oai_id
+        .strip_prefix(OAI_ID_PREFIX)
+        .ok_or_else(|| Ar5ivError::Oai(format!("unexpected identifier {oai_id:?}")))?;
+      ids.push(id.to_string());
+    }
+  }
+
+  // Check for a resumption token, in which case we recurse into the next set:
+  // <resumptionToken cursor=\"0\" completeListSize=\"34188\">6380191|10001</resumptionToken>
+  let resumption_nodes = root.findnodes("//*[local-name()='resumptionToken']", &doc).unwrap_or_default();
+  if let Some(resumption_node) = resumption_nodes.first() {
+    let resume_token = resumption_node.get_content();
+    if !resume_token.is_empty() {
+      ids.extend(fetch_article_list_resume(&resume_token)?);
     }
   }
   Ok(ids)
 }
 
-pub fn fetch_article_list_resume(token: &str) -> Result<Vec<String>, Box<dyn Error>> {
+pub fn fetch_article_list_resume(token: &str) -> Result<Vec<String>> {
   let oai_arxiv_resume_url = format!(
     "http://export.arxiv.org/oai2?verb=ListIdentifiers&resumptionToken={token}");
   fetch_article_list_by_url(oai_arxiv_resume_url)
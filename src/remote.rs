@@ -1,24 +1,211 @@
-use std::error::Error;
-use std::fs::File;
-use std::io::prelude::*;
-use std::path::Path;
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, prelude::*};
+use std::path::{Path, PathBuf};
 use std::thread;
 use std::time::Duration;
 
 use rayon::prelude::*;
 use reqwest::blocking::Client;
+use reqwest::header::CONTENT_DISPOSITION;
+use tracing::{debug, info_span, warn};
+
+use crate::error::{io_at, Ar5ivError, Result};
+use crate::metrics;
+
+/// Directory e-prints are spooled to while downloading, default: the system temp dir.
+pub const SPOOL_DIR_ENV: &str = "AR5IV_SPOOL_DIR";
+/// Largest e-print payload to accept, in bytes.
+pub const MAX_EPRINT_BYTES_ENV: &str = "AR5IV_MAX_EPRINT_BYTES";
+const DEFAULT_MAX_EPRINT_BYTES: u64 = 1 << 30;
+
+/// Where and how much of e-print payloads to spool to disk.
+#[derive(Debug, Clone)]
+pub struct Spool {
+  pub dir: PathBuf,
+  pub max_bytes: u64,
+}
+
+impl Spool {
+  /// The spool configured via `AR5IV_SPOOL_DIR` and `AR5IV_MAX_EPRINT_BYTES`.
+  pub fn from_env() -> Self {
+    Spool {
+      dir: env::var(SPOOL_DIR_ENV)
+        .map(PathBuf::from)
+        .unwrap_or_else(|_| env::temp_dir()),
+      max_bytes: env::var(MAX_EPRINT_BYTES_ENV)
+        .ok()
+        .and_then(|max| max.parse().ok())
+        .unwrap_or(DEFAULT_MAX_EPRINT_BYTES),
+    }
+  }
+
+  fn path_for(&self, arxiv_id: &str) -> PathBuf {
+    let name = arxiv_id.replace('/', "_");
+    self.dir.join(format!("ar5iv-{}-{name}.eprint", std::process::id()))
+  }
+}
+
+/// A downloaded e-print payload, spooled to a file that is removed when this is dropped.
+#[derive(Debug)]
+pub struct EPrint {
+  pub path: PathBuf,
+  pub len: u64,
+  /// The file name arXiv served it under, via `Content-Disposition`, e.g. `2101.00001v2.pdf`
+  pub filename: Option<String>,
+}
+
+impl Drop for EPrint {
+  fn drop(&mut self) { let _ = fs::remove_file(&self.path); }
+}
+
+/// What became of an attempt to download an article's e-print.
+#[derive(Debug)]
+pub enum DownloadOutcome {
+  Downloaded(EPrint),
+  /// HTTP 403, almost always a withdrawal at the author's request.
+  Forbidden,
+  /// The last attempt returned HTTP 200, but no bytes.
+  Empty,
+  /// The last attempt returned an unexpected HTTP status.
+  Status(u16),
+  /// The payload was larger than the spool's maximum.
+  TooLarge(u64),
+  /// The last attempt failed before a response arrived.
+  Network(String),
+}
+
+impl DownloadOutcome {
+  /// The label this outcome is counted under in metrics.
+  pub fn label(&self) -> &'static str {
+    match self {
+      DownloadOutcome::Downloaded(_) => "downloaded",
+      DownloadOutcome::Forbidden => "forbidden",
+      DownloadOutcome::Empty => "empty",
+      DownloadOutcome::Status(_) => "http_error",
+      DownloadOutcome::TooLarge(_) => "too_large",
+      DownloadOutcome::Network(_) => "network_error",
+    }
+  }
+
+  /// A short label for the reason a download did not succeed, `None` for successes.
+  pub fn failure_reason(&self) -> Option<String> {
+    match self {
+      DownloadOutcome::Downloaded(_) => None,
+      DownloadOutcome::Forbidden => Some(String::from("http 403")),
+      DownloadOutcome::Empty => Some(String::from("empty payload")),
+      DownloadOutcome::Status(code) => Some(format!("http {code}")),
+      DownloadOutcome::TooLarge(_) => Some(String::from("payload too large")),
+      DownloadOutcome::Network(_) => Some(String::from("network error")),
+    }
+  }
+}
+
+/// Download the latest e-print of `arxiv_id` into the `spool`, with up to three attempts.
+pub fn fetch_eprint(client: &Client, spool: &Spool, arxiv_id: &str) -> DownloadOutcome {
+  let outcome = fetch_eprint_attempts(client, spool, arxiv_id);
+  let bytes = match &outcome {
+    DownloadOutcome::Downloaded(eprint) => eprint.len as usize,
+    _ => 0,
+  };
+  metrics::record_download(outcome.label(), bytes);
+  outcome
+}
+
+fn fetch_eprint_attempts(client: &Client, spool: &Spool, arxiv_id: &str) -> DownloadOutcome {
+  let url = format!("https://export.arxiv.org/e-print/{arxiv_id}");
+  let _span = info_span!("fetch_eprint", paper = arxiv_id, url = %url).entered();
+  let mut outcome = DownloadOutcome::Empty;
+  for attempt in 1..=3 {
+    outcome = match client.get(&url).send() {
+      Ok(mut payload) => {
+        let code = payload.status().as_u16();
+        metrics::record_http_status("e-print", code);
+        let filename = payload
+          .headers()
+          .get(CONTENT_DISPOSITION)
+          .and_then(|value| value.to_str().ok())
+          .and_then(content_disposition_filename);
+        match code {
+          200 => {
+            let mut eprint = EPrint {
+              path: spool.path_for(arxiv_id),
+              len: 0,
+              filename,
+            };
+            // read one byte past the maximum, to tell a too large payload apart
+            match spool_payload(&mut payload, &eprint.path, spool.max_bytes + 1) {
+              Ok(len) if len > spool.max_bytes => {
+                warn!(attempt, status = code, max_bytes = spool.max_bytes, "too large, skip");
+                return DownloadOutcome::TooLarge(len);
+              },
+              Ok(len) if len > 0 => {
+                debug!(attempt, status = code, bytes = len, filename = eprint.filename, "spooled");
+                eprint.len = len;
+                return DownloadOutcome::Downloaded(eprint);
+              },
+              Ok(_) => {
+                warn!(attempt, status = code, "no bytes returned");
+                DownloadOutcome::Empty
+              },
+              Err(e) => {
+                warn!(attempt, status = code, error = ?e, "reading the payload failed");
+                DownloadOutcome::Network(e.to_string())
+              },
+            }
+          },
+          403 => {
+            warn!(attempt, status = code, "forbidden, skip");
+            return DownloadOutcome::Forbidden;
+          },
+          other => {
+            warn!(attempt, status = other, "unexpected status");
+            DownloadOutcome::Status(other)
+          },
+        }
+      },
+      Err(e) => {
+        warn!(attempt, error = ?e, "request failed");
+        DownloadOutcome::Network(e.to_string())
+      },
+    };
+  }
+  outcome
+}
+
+/// Stream a response body to `spool_path`, reading at most `max_bytes`.
+fn spool_payload(body: &mut impl Read, spool_path: &Path, max_bytes: u64) -> io::Result<u64> {
+  let mut spool_file = File::create(spool_path)?;
+  let len = io::copy(&mut body.take(max_bytes), &mut spool_file)?;
+  spool_file.flush()?;
+  Ok(len)
+}
+
+/// The `filename` parameter of a `Content-Disposition` header value, without any directory.
+fn content_disposition_filename(value: &str) -> Option<String> {
+  let filename = value
+    .split(';')
+    .filter_map(|param| param.trim().split_once('='))
+    .find(|(key, _)| key.trim().eq_ignore_ascii_case("filename"))?
+    .1
+    .trim()
+    .trim_matches('"');
+  let filename = filename.rsplit(['/', '\\']).next().unwrap_or_default();
+  (!filename.is_empty()).then(|| filename.to_owned())
+}
 
 pub fn check_ids_http(
   task_ids: Vec<String>,
   destination_filepath: &str,
-) -> Result<(), Box<dyn Error>> {
+) -> Result<()> {
   let destination_path = Path::new(destination_filepath);
   let mut dest_file = if destination_path.exists() {
     File::options()
       .append(true)
-      .open(destination_path)?
+      .open(destination_path)
+      .map_err(io_at(destination_path))?
   } else {
-    File::create(destination_path)?
+    File::create(destination_path).map_err(io_at(destination_path))?
   };
   let client = reqwest::blocking::Client::builder()
     .user_agent("ar5iv (https://ar5iv.labs.arxiv.org)")
@@ -29,45 +216,59 @@ pub fn check_ids_http(
       .par_iter()
       .map(|id| (id, fish_out_article_version(&client, id)))
       .collect();
+    // record what was checked before aborting, so that a rerun resumes after it.
+    let mut batch_error = None;
     for (id, version) in ids_with_versions {
-      writeln!(dest_file, "{id},{version}")?;
+      match version {
+        Ok(version) => writeln!(dest_file, "{id},{version}").map_err(io_at(destination_path))?,
+        Err(e) => batch_error = batch_error.or(Some(e)),
+      }
+    }
+    if let Some(e) = batch_error {
+      return Err(e);
     }
     thread::sleep(Duration::from_secs(1));
   }
   Ok(())
 }
 
-fn fish_out_article_version(client: &Client, arxiv_id: &str) -> usize {
+fn fish_out_article_version(client: &Client, arxiv_id: &str) -> Result<usize> {
   // try incrementing until we get a 404 for a version (also, we know v1 exists)
   let mut version_try = 2;
   let mut export_arxiv_url = format!("https://export.arxiv.org/abs/{arxiv_id}v{version_try}");
-  while retry_check_url(client, &export_arxiv_url) {
+  while retry_check_url(client, &export_arxiv_url)? {
     version_try += 1;
     export_arxiv_url = format!("https://export.arxiv.org/abs/{arxiv_id}v{version_try}");
     thread::sleep(Duration::from_secs(1));
   }
-  version_try - 1
+  Ok(version_try - 1)
 }
 
 // We have a simple and efficient check:
 // if the "/abs/IDvN" URL resolves with HTTP 200
 // then version N exists. 404 (or other), we assume it doesn't.
-fn retry_check_url(client: &Client, url: &str) -> bool {
+// A 403 means this scraper has been forbidden from accessing export.arxiv.org, and a failure to
+// connect means we can't reach it at all; both are errors the caller should abort on.
+fn retry_check_url(client: &Client, url: &str) -> Result<bool> {
   for _retries in 0..3 {
     match client.head(url).send() {
-      Ok(resp) => match resp.status().as_u16() {
-        200 => return true,
-        403 => panic!("This scraper has been forbidden from accessing export.arxiv.org, please contact an arXiv admin."),
-        400 | 404 => return false,
-        503 | 500 => thread::sleep(Duration::from_secs(10)),
-        other => eprintln!("-- no handler for http code {other}.")
+      Ok(resp) => {
+        let code = resp.status().as_u16();
+        metrics::record_http_status("abs", code);
+        match code {
+          200 => return Ok(true),
+          403 => return Err(Ar5ivError::HttpStatus { url: url.to_owned(), status: code }),
+          400 | 404 => return Ok(false),
+          503 | 500 => thread::sleep(Duration::from_secs(10)),
+          other => warn!(url, status = other, "no handler for http code"),
+        }
       },
       Err(e) => {
         if e.is_connect() {
-          panic!("Failed to connect, aborting. Reason: {e:?}");
+          return Err(e.into());
         }
       }
     }
   }
-  false
+  Ok(false)
 }
@@ -0,0 +1,317 @@
+//! A summary of each daily update run, kept as Markdown and HTML next to the oai id log.
+//!
+//! The harvest (`cron_update`) and the download (`update_arxiv_sources`) run as separate
+//! processes, so the report is also kept as JSON, which each of them loads and adds to.
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, prelude::*};
+use std::path::{Path, PathBuf};
+
+use chrono::Utc;
+use chrono_tz::America::New_York;
+use serde::{Deserialize, Serialize};
+
+use crate::error::{io_at, Result};
+use crate::local::RepackageReport;
+use crate::remote::DownloadOutcome;
+
+/// Shown for the counts of steps the run did not take
+const NOT_RUN: &str = "not run";
+
+/// How many kinds of errors to list in a report.
+const TOP_ERRORS: usize = 10;
+
+/// What fetching the sources of the harvested ids did.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct FetchStats {
+  /// Repackaged papers new to the corpus
+  pub new_papers: usize,
+  /// Repackaged papers already in the corpus, whose sources changed
+  pub updated_papers: usize,
+  /// Re-downloads whose sources matched the existing zip, left alone
+  pub unchanged_papers: usize,
+  /// Downloads that could not be repackaged
+  pub repackage_failed: usize,
+  pub downloads_succeeded: usize,
+  /// Failed downloads, counted by reason
+  pub downloads_failed: HashMap<String, usize>,
+  /// Ids answered with HTTP 403, usually withdrawn
+  pub forbidden_ids: Vec<String>,
+  /// Ids whose sources are not TeX (PDF-only, HTML, ...), not sent to conversion
+  pub non_tex_ids: Vec<String>,
+  /// Ids whose TeX sources are new or changed, to be (re-)converted
+  pub changed_tex_ids: Vec<String>,
+  pub bytes_transferred: u64,
+}
+
+impl FetchStats {
+  /// Whether every failed download was a withdrawn paper, an expected outcome.
+  pub fn only_withdrawn_failed(&self) -> bool {
+    self.downloads_failed.values().sum::<usize>() == self.forbidden_ids.len()
+  }
+}
+
+/// Errors of one kind, with the first message seen as an example.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ErrorCount {
+  pub count: usize,
+  pub example: String,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct RunReport {
+  /// The (Eastern time) date of the run, as YYYY-MM-DD
+  pub date: String,
+  pub harvested: usize,
+  /// `None` if no sources were fetched that day
+  pub fetch: Option<FetchStats>,
+  pub duration_secs: u64,
+  /// Errors, counted by kind
+  pub errors: HashMap<String, ErrorCount>,
+}
+
+impl RunReport {
+  pub fn new(date: &str) -> Self {
+    RunReport {
+      date: date.to_owned(),
+      ..RunReport::default()
+    }
+  }
+
+  /// The report of `date` kept in `log_dir` so far, or a new one.
+  pub fn load_or_new(log_dir: &str, date: &str) -> Result<Self> {
+    let json_path = report_path(log_dir, date, "json");
+    match fs::read(&json_path) {
+      Ok(json) => Ok(serde_json::from_slice(&json)?),
+      Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(RunReport::new(date)),
+      Err(e) => Err(io_at(&json_path)(e)),
+    }
+  }
+
+  /// Count a download attempt for `arxiv_id`.
+  pub fn record_download(&mut self, arxiv_id: &str, outcome: &DownloadOutcome) {
+    let fetch = self.fetch.get_or_insert_with(FetchStats::default);
+    match outcome {
+      DownloadOutcome::Downloaded(eprint) => {
+        fetch.downloads_succeeded += 1;
+        fetch.bytes_transferred += eprint.len;
+      },
+      DownloadOutcome::Forbidden => fetch.forbidden_ids.push(arxiv_id.to_owned()),
+      DownloadOutcome::Empty
+      | DownloadOutcome::Status(_)
+      | DownloadOutcome::TooLarge(_)
+      | DownloadOutcome::Network(_) => {},
+    }
+    if let Some(reason) = outcome.failure_reason() {
+      *fetch.downloads_failed.entry(reason).or_insert(0) += 1;
+    }
+    if let DownloadOutcome::Network(message) = outcome {
+      self.record_error("network", message);
+    }
+  }
+
+  /// Count a downloaded paper as repackaged; `is_new` if it was not in the corpus yet.
+  pub fn record_repackaged(&mut self, arxiv_id: &str, is_new: bool, report: &RepackageReport) {
+    let fetch = self.fetch.get_or_insert_with(FetchStats::default);
+    if report.unchanged {
+      fetch.unchanged_papers += 1;
+      return;
+    }
+    if is_new {
+      fetch.new_papers += 1;
+    } else {
+      fetch.updated_papers += 1;
+    }
+    if report.class.is_tex() {
+      fetch.changed_tex_ids.push(arxiv_id.to_owned());
+    } else {
+      fetch.non_tex_ids.push(arxiv_id.to_owned());
+    }
+  }
+
+  /// Count a downloaded paper that could not be repackaged, as an error of `kind`.
+  pub fn record_repackage_failure(&mut self, kind: &str, message: &str) {
+    self.fetch.get_or_insert_with(FetchStats::default).repackage_failed += 1;
+    self.record_error(kind, message);
+  }
+
+  pub fn record_error(&mut self, kind: &str, message: &str) {
+    self
+      .errors
+      .entry(kind.to_owned())
+      .or_insert_with(|| ErrorCount {
+        count: 0,
+        example: message.to_owned(),
+      })
+      .count += 1;
+  }
+
+  /// The most frequent kinds of errors first, at most `TOP_ERRORS` of them.
+  pub fn top_errors(&self) -> Vec<(&str, &ErrorCount)> {
+    let mut errors: Vec<(&str, &ErrorCount)> = self
+      .errors
+      .iter()
+      .map(|(kind, count)| (kind.as_str(), count))
+      .collect();
+    errors.sort_by(|a, b| b.1.count.cmp(&a.1.count).then(a.0.cmp(b.0)));
+    errors.truncate(TOP_ERRORS);
+    errors
+  }
+
+  fn summary_rows(&self) -> Vec<(&'static str, String)> {
+    let fetched = |count: fn(&FetchStats) -> String| {
+      self
+        .fetch
+        .as_ref()
+        .map_or_else(|| String::from(NOT_RUN), count)
+    };
+    vec![
+      ("Ids harvested via OAI", self.harvested.to_string()),
+      ("New papers", fetched(|fetch| fetch.new_papers.to_string())),
+      ("Updated papers", fetched(|fetch| fetch.updated_papers.to_string())),
+      ("Unchanged sources", fetched(|fetch| fetch.unchanged_papers.to_string())),
+      ("Repackaging failed", fetched(|fetch| fetch.repackage_failed.to_string())),
+      ("Downloads succeeded", fetched(|fetch| fetch.downloads_succeeded.to_string())),
+      (
+        "Downloads failed",
+        fetched(|fetch| fetch.downloads_failed.values().sum::<usize>().to_string()),
+      ),
+      ("Withdrawn (HTTP 403)", fetched(|fetch| fetch.forbidden_ids.len().to_string())),
+      (
+        "Not TeX, skipped for conversion",
+        fetched(|fetch| fetch.non_tex_ids.len().to_string()),
+      ),
+      (
+        "TeX new or changed, to (re-)convert",
+        fetched(|fetch| fetch.changed_tex_ids.len().to_string()),
+      ),
+      ("Bytes transferred", fetched(|fetch| fetch.bytes_transferred.to_string())),
+      ("Duration (sec)", self.duration_secs.to_string()),
+    ]
+  }
+
+  fn sorted_failures(&self) -> Vec<(&str, usize)> {
+    let mut failures: Vec<(&str, usize)> = self
+      .fetch
+      .iter()
+      .flat_map(|fetch| fetch.downloads_failed.iter())
+      .map(|(reason, count)| (reason.as_str(), *count))
+      .collect();
+    failures.sort();
+    failures
+  }
+
+  fn forbidden_ids(&self) -> &[String] {
+    self
+      .fetch
+      .as_ref()
+      .map_or(&[], |fetch| fetch.forbidden_ids.as_slice())
+  }
+
+  pub fn to_markdown(&self) -> String {
+    let mut md = format!("# ar5iv update run {}\n\n| | |\n|---|---|\n", self.date);
+    for (label, value) in self.summary_rows() {
+      md.push_str(&format!("| {label} | {value} |\n"));
+    }
+    let failures = self.sorted_failures();
+    if !failures.is_empty() {
+      md.push_str("\n## Failed downloads by reason\n\n");
+      for (reason, count) in failures {
+        md.push_str(&format!("- {reason}: {count}\n"));
+      }
+    }
+    if !self.forbidden_ids().is_empty() {
+      md.push_str("\n## Withdrawn papers\n\n");
+      for id in self.forbidden_ids() {
+        md.push_str(&format!("- {id}\n"));
+      }
+    }
+    let top_errors = self.top_errors();
+    if !top_errors.is_empty() {
+      md.push_str("\n## Top errors\n\n");
+      for (kind, errors) in top_errors {
+        md.push_str(&format!("- {kind} ({}x), e.g. `{}`\n", errors.count, errors.example));
+      }
+    }
+    md
+  }
+
+  pub fn to_html(&self) -> String {
+    let title = format!("ar5iv update run {}", escape_html(&self.date));
+    let mut html = format!(
+      "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{title}</title>\n\
+       <style>body{{font-family:sans-serif}} td{{padding:0 1em}}</style>\n</head>\n<body>\n\
+       <h1>{title}</h1>\n<table>\n"
+    );
+    for (label, value) in self.summary_rows() {
+      html.push_str(&format!("<tr><td>{label}</td><td>{value}</td></tr>\n"));
+    }
+    html.push_str("</table>\n");
+    let failures = self.sorted_failures();
+    if !failures.is_empty() {
+      html.push_str("<h2>Failed downloads by reason</h2>\n<ul>\n");
+      for (reason, count) in failures {
+        html.push_str(&format!("<li>{}: {count}</li>\n", escape_html(reason)));
+      }
+      html.push_str("</ul>\n");
+    }
+    if !self.forbidden_ids().is_empty() {
+      html.push_str("<h2>Withdrawn papers</h2>\n<ul>\n");
+      for id in self.forbidden_ids() {
+        let id = escape_html(id);
+        html.push_str(&format!(
+          "<li><a href=\"https://arxiv.org/abs/{id}\">{id}</a></li>\n"
+        ));
+      }
+      html.push_str("</ul>\n");
+    }
+    let top_errors = self.top_errors();
+    if !top_errors.is_empty() {
+      html.push_str("<h2>Top errors</h2>\n<ul>\n");
+      for (kind, errors) in top_errors {
+        html.push_str(&format!(
+          "<li>{} ({}x), e.g. <code>{}</code></li>\n",
+          escape_html(kind),
+          errors.count,
+          escape_html(&errors.example)
+        ));
+      }
+      html.push_str("</ul>\n");
+    }
+    html.push_str("</body>\n</html>\n");
+    html
+  }
+
+  /// Writes `run_report_{date}.md`, `.html` and `.json` into `log_dir`.
+  pub fn write_to_log_dir(&self, log_dir: &str) -> Result<()> {
+    let md_path = report_path(log_dir, &self.date, "md");
+    File::create(&md_path)
+      .and_then(|mut md_file| md_file.write_all(self.to_markdown().as_bytes()))
+      .map_err(io_at(&md_path))?;
+    let html_path = report_path(log_dir, &self.date, "html");
+    File::create(&html_path)
+      .and_then(|mut html_file| html_file.write_all(self.to_html().as_bytes()))
+      .map_err(io_at(&html_path))?;
+    // the other process may load it at any time, so replace it whole
+    let json_path = report_path(log_dir, &self.date, "json");
+    let tmp_path = json_path.with_extension("json.tmp");
+    fs::write(&tmp_path, serde_json::to_vec_pretty(self)?).map_err(io_at(&tmp_path))?;
+    fs::rename(&tmp_path, &json_path).map_err(io_at(&json_path))?;
+    Ok(())
+  }
+}
+
+fn report_path(log_dir: &str, date: &str, extension: &str) -> PathBuf {
+  Path::new(log_dir).join(format!("run_report_{date}.{extension}"))
+}
+
+/// Today's date in arXiv's (Eastern) time zone, as YYYY-MM-DD
+pub fn eastern_today() -> String { Utc::now().with_timezone(&New_York).date_naive().to_string() }
+
+fn escape_html(text: &str) -> String {
+  text
+    .replace('&', "&amp;")
+    .replace('<', "&lt;")
+    .replace('>', "&gt;")
+    .replace('"', "&quot;")
+}
@@ -0,0 +1,84 @@
+//! arXiv's announcement calendar, used to time the daily update run.
+//!
+//! New submissions are announced at 20:00 America/New_York, Sunday through Thursday,
+//! except on the holidays arXiv publishes ahead of time at
+//! https://info.arxiv.org/help/availability.html
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{prelude::*, BufReader};
+use std::path::Path;
+
+use chrono::{DateTime, Datelike, Duration, NaiveDate, NaiveTime, TimeZone, Utc, Weekday};
+use chrono_tz::America::New_York;
+use chrono_tz::Tz;
+
+use crate::error::{io_at, Result};
+
+/// One `YYYY-MM-DD` per line, `#` starts a comment.
+pub const ARXIV_HOLIDAYS_FILEPATH: &str = "arxiv_holidays.txt";
+pub const NEXT_RUN_FILEPATH: &str = "next_run.txt";
+pub const ANNOUNCEMENT_HOUR: u32 = 20;
+
+pub struct AnnouncementSchedule {
+  holidays: HashSet<NaiveDate>,
+  delay: Duration,
+}
+
+impl AnnouncementSchedule {
+  /// A schedule firing `delay` after every announcement.
+  pub fn new(delay: Duration) -> Self {
+    AnnouncementSchedule {
+      holidays: HashSet::new(),
+      delay,
+    }
+  }
+
+  /// Skip the holidays listed in `holidays_filepath`, if that file exists.
+  pub fn with_holidays_file(mut self, holidays_filepath: &str) -> Result<Self> {
+    if !Path::new(holidays_filepath).exists() {
+      return Ok(self);
+    }
+    let reader = BufReader::new(File::open(holidays_filepath).map_err(io_at(holidays_filepath))?);
+    for line in reader.lines().map_while(std::result::Result::ok) {
+      let date_str = line.split('#').next().unwrap_or_default().trim();
+      if !date_str.is_empty() {
+        self
+          .holidays
+          .insert(NaiveDate::parse_from_str(date_str, "%Y-%m-%d")?);
+      }
+    }
+    Ok(self)
+  }
+
+  pub fn is_announcement_day(&self, date: NaiveDate) -> bool {
+    !matches!(date.weekday(), Weekday::Fri | Weekday::Sat) && !self.holidays.contains(&date)
+  }
+
+  /// The announcement time on `date` (Eastern time, so DST shifts are accounted for),
+  /// if arXiv announces that day.
+  pub fn announcement_on(&self, date: NaiveDate) -> Option<DateTime<Tz>> {
+    if !self.is_announcement_day(date) {
+      return None;
+    }
+    let announce_time = NaiveTime::from_hms_opt(ANNOUNCEMENT_HOUR, 0, 0)?;
+    New_York
+      .from_local_datetime(&date.and_time(announce_time))
+      .single()
+  }
+
+  /// The first planned run strictly after `now`.
+  pub fn next_run_after(&self, now: DateTime<Utc>) -> DateTime<Tz> {
+    // a long delay can push an earlier announcement's run past `now`, so start looking back
+    let mut date =
+      now.with_timezone(&New_York).date_naive() - Duration::days(self.delay.num_days() + 1);
+    loop {
+      if let Some(announced) = self.announcement_on(date) {
+        let planned = announced + self.delay;
+        if planned > now {
+          return planned;
+        }
+      }
+      date = date.succ_opt().expect("announcement dates stay within chrono's range");
+    }
+  }
+}
@@ -0,0 +1,300 @@
+//! The archive backends, held to the same behavior: every test runs against each backend
+//! compiled in, and the fixtures are read by all of them alike. Run with
+//! `cargo test --features pure-rust` to cover both.
+use std::io::prelude::*;
+use std::path::Path;
+
+use flate2::write::GzEncoder;
+use flate2::Compression;
+
+use ar5iv_util::local::archive::{
+  ArchiveBackend, ArchiveReader, EntryHeader, ReadMode, WriteFormat,
+};
+
+fn backends() -> Vec<&'static dyn ArchiveBackend> {
+  vec![
+    #[cfg(feature = "libarchive")]
+    &ar5iv_util::local::archive::libarchive::LibArchive,
+    #[cfg(feature = "pure-rust")]
+    &ar5iv_util::local::archive::pure::PureRust,
+  ]
+}
+
+/// Every entry of an archive, as (header, data), failing on any read error.
+fn read_all(reader: &mut dyn ArchiveReader) -> std::io::Result<Vec<(EntryHeader, Vec<u8>)>> {
+  let mut entries = Vec::new();
+  while let Some(header) = reader.next_entry()? {
+    let mut data = Vec::new();
+    while let Some(chunk) = reader.read_chunk()? {
+      data.extend(chunk);
+    }
+    entries.push((header, data));
+  }
+  Ok(entries)
+}
+
+fn read_memory(
+  backend: &dyn ArchiveBackend,
+  memory: &[u8],
+  mode: ReadMode,
+) -> std::io::Result<Vec<(EntryHeader, Vec<u8>)>> {
+  let mut memory = memory.to_vec();
+  let mut reader = backend.open_memory(&mut memory, mode)?;
+  read_all(reader.as_mut())
+}
+
+fn gzip(data: &[u8]) -> Vec<u8> {
+  let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+  encoder.write_all(data).unwrap();
+  encoder.finish().unwrap()
+}
+
+fn tar_header(path: &str, kind: tar::EntryType, size: u64) -> tar::Header {
+  let mut header = tar::Header::new_gnu();
+  header.set_path(path).unwrap();
+  header.set_entry_type(kind);
+  header.set_size(size);
+  header.set_mode(0o644);
+  header.set_mtime(1_600_000_000);
+  header.set_cksum();
+  header
+}
+
+/// A tar.gz with a directory, two files, a symlink and a hardlink.
+fn linked_tar_gz() -> Vec<u8> {
+  let mut builder = tar::Builder::new(Vec::new());
+  builder
+    .append(&tar_header("figs/", tar::EntryType::Directory, 0), &[][..])
+    .unwrap();
+  let main = b"\\documentclass{article}\\input{figs/plot}";
+  builder
+    .append(
+      &tar_header("main.tex", tar::EntryType::Regular, main.len() as u64),
+      &main[..],
+    )
+    .unwrap();
+  let plot = b"\\begin{picture}\\end{picture}";
+  builder
+    .append(
+      &tar_header("figs/plot.tex", tar::EntryType::Regular, plot.len() as u64),
+      &plot[..],
+    )
+    .unwrap();
+  let mut symlink = tar_header("plot.tex", tar::EntryType::Symlink, 0);
+  symlink.set_link_name("figs/plot.tex").unwrap();
+  symlink.set_cksum();
+  builder.append(&symlink, &[][..]).unwrap();
+  let mut hardlink = tar_header("copy.tex", tar::EntryType::Link, 0);
+  hardlink.set_link_name("main.tex").unwrap();
+  hardlink.set_cksum();
+  builder.append(&hardlink, &[][..]).unwrap();
+  gzip(&builder.into_inner().unwrap())
+}
+
+fn zip_of(files: &[(&str, &[u8])]) -> Vec<u8> {
+  let mut zip = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+  let options =
+    zip::write::FileOptions::default().compression_method(zip::CompressionMethod::Deflated);
+  for (path, data) in files {
+    zip.start_file(*path, options).unwrap();
+    zip.write_all(data).unwrap();
+  }
+  zip.finish().unwrap().into_inner()
+}
+
+/// The entries' paths, with their data as text.
+fn listing(entries: &[(EntryHeader, Vec<u8>)]) -> Vec<(String, String)> {
+  entries
+    .iter()
+    .map(|(header, data)| {
+      (
+        header.pathname.clone(),
+        String::from_utf8_lossy(data).into_owned(),
+      )
+    })
+    .collect()
+}
+
+#[test]
+fn reads_tar_gz_with_links() {
+  let archive = linked_tar_gz();
+  for backend in backends() {
+    let entries = read_memory(backend, &archive, ReadMode::Archive).unwrap();
+    let paths: Vec<&str> = entries
+      .iter()
+      .map(|(header, _)| header.pathname.as_str())
+      .collect();
+    assert_eq!(
+      paths,
+      ["figs/", "main.tex", "figs/plot.tex", "plot.tex", "copy.tex"],
+      "{}",
+      backend.name()
+    );
+    assert_eq!(
+      entries[1].1,
+      b"\\documentclass{article}\\input{figs/plot}",
+      "{}",
+      backend.name()
+    );
+    assert_eq!(entries[1].0.mtime, 1_600_000_000, "{}", backend.name());
+    assert_eq!(
+      entries[3].0.symlink.as_deref(),
+      Some("figs/plot.tex"),
+      "{}",
+      backend.name()
+    );
+    assert_eq!(entries[3].0.hardlink, None, "{}", backend.name());
+    assert_eq!(
+      entries[4].0.hardlink.as_deref(),
+      Some("main.tex"),
+      "{}",
+      backend.name()
+    );
+    assert!(entries[4].1.is_empty(), "{}", backend.name());
+  }
+}
+
+#[test]
+fn reads_single_gz_raw() {
+  let tex = b"\\documentclass{article}\\begin{document}x\\end{document}";
+  let compressed = gzip(tex);
+  for backend in backends() {
+    let archive_entries = read_memory(backend, &compressed, ReadMode::Archive).unwrap_or_default();
+    assert!(archive_entries.is_empty(), "{}", backend.name());
+    let entries = read_memory(backend, &compressed, ReadMode::Raw).unwrap();
+    assert_eq!(
+      listing(&entries),
+      [(
+        String::from("data"),
+        String::from_utf8_lossy(tex).into_owned()
+      )],
+      "{}",
+      backend.name()
+    );
+  }
+}
+
+#[test]
+fn reads_uncompressed_raw() {
+  let tex = b"\\documentclass{article}";
+  for backend in backends() {
+    let entries = read_memory(backend, tex, ReadMode::Raw).unwrap();
+    assert_eq!(
+      listing(&entries),
+      [(
+        String::from("data"),
+        String::from("\\documentclass{article}")
+      )],
+      "{}",
+      backend.name()
+    );
+  }
+}
+
+#[test]
+fn reads_zip() {
+  let archive = zip_of(&[
+    ("main.tex", b"\\documentclass{article}"),
+    ("sub/a.tex", b"a"),
+  ]);
+  for backend in backends() {
+    let entries = read_memory(backend, &archive, ReadMode::Archive).unwrap();
+    assert_eq!(
+      listing(&entries),
+      [
+        (
+          String::from("main.tex"),
+          String::from("\\documentclass{article}")
+        ),
+        (String::from("sub/a.tex"), String::from("a"))
+      ],
+      "{}",
+      backend.name()
+    );
+  }
+}
+
+#[test]
+fn reports_corrupt_zip() {
+  let data = b"\\documentclass{article} with enough text to be deflated rather than stored";
+  let mut archive = zip_of(&[("main.tex", data)]);
+  // flip a byte of the stored CRC-32, in the local header and the central directory alike
+  let mut crc = flate2::Crc::new();
+  crc.update(data);
+  let crc = crc.sum().to_le_bytes();
+  for offset in 0..archive.len() - 4 {
+    if archive[offset..offset + 4] == crc {
+      archive[offset] ^= 0xff;
+    }
+  }
+  for backend in backends() {
+    assert!(
+      read_memory(backend, &archive, ReadMode::Archive).is_err(),
+      "{}",
+      backend.name()
+    );
+  }
+}
+
+#[test]
+fn reports_truncated_tar_gz() {
+  let archive = linked_tar_gz();
+  let truncated = &archive[..archive.len() / 2];
+  for backend in backends() {
+    assert!(
+      read_memory(backend, truncated, ReadMode::Archive).is_err(),
+      "{}",
+      backend.name()
+    );
+  }
+}
+
+#[test]
+fn write_round_trips() {
+  let files = [
+    ("main.tex", b"\\documentclass{article}".to_vec()),
+    ("figs/plot.pdf", vec![0, 1, 2, 255]),
+    ("empty.bbl", Vec::new()),
+  ];
+  let dir = std::env::temp_dir().join(format!("ar5iv-archive-backends-{}", std::process::id()));
+  std::fs::create_dir_all(&dir).unwrap();
+  for writer_backend in backends() {
+    for (format, extension) in [
+      (WriteFormat::Zip, "zip"),
+      (WriteFormat::TarZstd, "tar.zst"),
+      (WriteFormat::TarGzip, "tar.gz"),
+    ] {
+      let path = dir.join(format!("{}.{extension}", writer_backend.name()));
+      {
+        let mut writer = writer_backend.create(&path, format).unwrap();
+        for (name, data) in files.iter() {
+          writer.write_entry(name, data.clone()).unwrap();
+        }
+      } // dropping the writer closes the archive
+        // what one backend writes, every backend reads back
+      for reader_backend in backends() {
+        let entries = read_file(reader_backend, &path);
+        let read: Vec<(String, Vec<u8>)> = entries
+          .into_iter()
+          .map(|(header, data)| (header.pathname, data))
+          .collect();
+        let expected: Vec<(String, Vec<u8>)> = files
+          .iter()
+          .map(|(name, data)| (name.to_string(), data.clone()))
+          .collect();
+        assert_eq!(
+          read,
+          expected,
+          "{extension} written by {}, read by {}",
+          writer_backend.name(),
+          reader_backend.name()
+        );
+      }
+    }
+  }
+  std::fs::remove_dir_all(&dir).unwrap();
+}
+
+fn read_file(backend: &dyn ArchiveBackend, path: &Path) -> Vec<(EntryHeader, Vec<u8>)> {
+  read_all(backend.open_file(path, ReadMode::Archive).unwrap().as_mut()).unwrap()
+}
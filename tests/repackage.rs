@@ -0,0 +1,185 @@
+//! Repackaging e-prints end to end, into a scratch corpus directory, with the archive backend
+//! compiled in.
+use std::fs;
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+
+use flate2::write::GzEncoder;
+use flate2::Compression;
+
+use ar5iv_util::local::limits::{LimitExceeded, RepackageLimits};
+use ar5iv_util::local::{
+  repackage_arxiv_download, repackage_eprint, EPrintSource, RepackageError, RepackageOptions,
+};
+
+/// An empty scratch directory, unique to the test.
+fn scratch_dir(test: &str) -> PathBuf {
+  let dir = std::env::temp_dir().join(format!("ar5iv-repackage-{}-{test}", std::process::id()));
+  let _ = fs::remove_dir_all(&dir);
+  fs::create_dir_all(&dir).unwrap();
+  dir
+}
+
+fn gzip(data: &[u8]) -> Vec<u8> {
+  let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+  encoder.write_all(data).unwrap();
+  encoder.finish().unwrap()
+}
+
+/// Bytes that do not compress, so that a cut into the gzip stream is a cut into the tar too.
+fn noise(len: usize, seed: u64) -> Vec<u8> {
+  let mut state = seed;
+  (0..len)
+    .map(|_| {
+      state = state
+        .wrapping_mul(6364136223846793005)
+        .wrapping_add(1442695040888963407);
+      (state >> 56) as u8
+    })
+    .collect()
+}
+
+/// A tar.gz of a main file followed by two figures.
+fn tar_gz() -> Vec<u8> {
+  let mut builder = tar::Builder::new(Vec::new());
+  let files = [
+    (
+      "main.tex",
+      b"\\documentclass{article}\\begin{document}x\\end{document}".to_vec(),
+    ),
+    ("fig1.png", noise(64 << 10, 1)),
+    ("fig2.png", noise(64 << 10, 2)),
+  ];
+  for (path, data) in files.iter() {
+    let mut header = tar::Header::new_gnu();
+    header.set_path(path).unwrap();
+    header.set_size(data.len() as u64);
+    header.set_mode(0o644);
+    header.set_cksum();
+    builder.append(&header, &data[..]).unwrap();
+  }
+  gzip(&builder.into_inner().unwrap())
+}
+
+fn repackage(dir: &Path, payload: &[u8]) -> Result<(), RepackageError> {
+  let mut memory = payload.to_vec();
+  let to_dir = dir.display().to_string();
+  repackage_arxiv_download(&mut memory, to_dir, String::from("2301.00001")).map(|_| ())
+}
+
+#[test]
+fn truncated_tar_gz_keeps_the_previous_output() {
+  let dir = scratch_dir("truncated");
+  let archive = tar_gz();
+  repackage(&dir, &archive).unwrap();
+  let zip_path = dir.join("2301.00001.zip");
+  let hash_path = dir.join("2301.00001.sha256");
+  let (zip, hash) = (fs::read(&zip_path).unwrap(), fs::read(&hash_path).unwrap());
+
+  let truncated = &archive[..archive.len() * 2 / 3];
+  let result = repackage(&dir, truncated);
+  assert!(
+    matches!(result, Err(RepackageError::Archive { .. })),
+    "{result:?}"
+  );
+  assert_eq!(fs::read(&zip_path).unwrap(), zip);
+  assert_eq!(fs::read(&hash_path).unwrap(), hash);
+  fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn truncated_single_gz_fails() {
+  let dir = scratch_dir("truncated-gz");
+  let mut tex = b"\\documentclass{article}\\begin{document}".to_vec();
+  tex.extend(noise(64 << 10, 3));
+  let compressed = gzip(&tex);
+  let result = repackage(&dir, &compressed[..compressed.len() / 2]);
+  assert!(
+    matches!(result, Err(RepackageError::Archive { .. })),
+    "{result:?}"
+  );
+  assert!(!dir.join("2301.00001.zip").exists());
+  fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn links_resolve_to_targets_read_after_them() {
+  let dir = scratch_dir("links");
+  let mut builder = tar::Builder::new(Vec::new());
+  for (path, kind, target) in [
+    ("alias.tex", tar::EntryType::Symlink, "main.tex"),
+    ("copy.tex", tar::EntryType::Link, "main.tex"),
+    ("chain.tex", tar::EntryType::Symlink, "alias.tex"),
+    ("loose.tex", tar::EntryType::Symlink, "missing.tex"),
+  ] {
+    let mut header = tar::Header::new_gnu();
+    header.set_path(path).unwrap();
+    header.set_entry_type(kind);
+    header.set_link_name(target).unwrap();
+    header.set_size(0);
+    header.set_cksum();
+    builder.append(&header, &[][..]).unwrap();
+  }
+  let tex = b"\\documentclass{article}\\begin{document}x\\end{document}";
+  let mut header = tar::Header::new_gnu();
+  header.set_path("main.tex").unwrap();
+  header.set_size(tex.len() as u64);
+  header.set_mode(0o644);
+  header.set_cksum();
+  builder.append(&header, &tex[..]).unwrap();
+  repackage(&dir, &gzip(&builder.into_inner().unwrap())).unwrap();
+
+  let zip_file = fs::File::open(dir.join("2301.00001.zip")).unwrap();
+  let mut zip = zip::ZipArchive::new(zip_file).unwrap();
+  for name in ["alias.tex", "copy.tex", "chain.tex"] {
+    let mut data = Vec::new();
+    zip.by_name(name).unwrap().read_to_end(&mut data).unwrap();
+    assert_eq!(data, tex, "{name}");
+  }
+  assert!(zip.by_name("loose.tex").is_err());
+  fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn spooled_reader_stops_at_the_payload_limit() {
+  let dir = scratch_dir("spool");
+  let archive = tar_gz();
+  let options = RepackageOptions {
+    limits: RepackageLimits {
+      max_payload_bytes: archive.len() as u64 - 1,
+      ..RepackageLimits::default()
+    },
+    ..RepackageOptions::default()
+  };
+  let source = EPrintSource::Reader(Box::new(&archive[..]));
+  let result = repackage_eprint(source, dir.display().to_string(), "2301.00001".into(), &options);
+  assert!(
+    matches!(
+      result,
+      Err(RepackageError::LimitExceeded { limit: LimitExceeded::PayloadBytes(_), .. })
+    ),
+    "{result:?}"
+  );
+  assert_eq!(fs::read_dir(&dir).unwrap().count(), 0);
+
+  let options = RepackageOptions {
+    limits: RepackageLimits {
+      max_payload_bytes: archive.len() as u64,
+      ..RepackageLimits::default()
+    },
+    ..RepackageOptions::default()
+  };
+  let source = EPrintSource::Reader(Box::new(&archive[..]));
+  repackage_eprint(source, dir.display().to_string(), "2301.00001".into(), &options).unwrap();
+  assert!(dir.join("2301.00001.zip").exists());
+  fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn plain_tex_is_read_raw() {
+  let dir = scratch_dir("plain");
+  let tex = b"\\documentclass{article}\\begin{document}x\\end{document}\n";
+  repackage(&dir, tex).unwrap();
+  assert!(dir.join("2301.00001.zip").exists());
+  fs::remove_dir_all(&dir).unwrap();
+}